@@ -0,0 +1,193 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+use crate::imaging::ImageError;
+use crate::{Error, Result};
+
+
+/// 发串时的附图，可来自内存、文件或异步读取流，并带有明确的文件名和 MIME 类型。
+/// 来自流的附图会以流的形式直接写入 multipart 请求体，不会先读入内存。
+pub struct Attachment {
+    pub file_name: String,
+    pub mime: String,
+    source: AttachmentSource,
+}
+
+pub(crate) enum AttachmentSource {
+    Bytes(Bytes),
+    File(PathBuf),
+    Reader {
+        reader: Pin<Box<dyn AsyncRead + Send>>,
+        length: Option<u64>,
+    },
+}
+
+impl Debug for Attachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match &self.source {
+            AttachmentSource::Bytes(b) => format!("Bytes({} bytes)", b.len()),
+            AttachmentSource::File(p) => format!("File({})", p.display()),
+            AttachmentSource::Reader { length, .. } => format!("Reader({length:?})"),
+        };
+        f.debug_struct("Attachment")
+            .field("file_name", &self.file_name)
+            .field("mime", &self.mime)
+            .field("source", &source)
+            .finish()
+    }
+}
+
+impl Attachment {
+
+    // 内存中的图片，Vec<u8>、Bytes、&'static [u8] 均可
+    pub fn from_bytes(data: impl Into<Bytes>, file_name: impl Into<String>, mime: impl Into<String>) -> Self {
+        Self {
+            file_name: file_name.into(),
+            mime: mime.into(),
+            source: AttachmentSource::Bytes(data.into()),
+        }
+    }
+
+    // 本地文件，发送时才打开并以流的形式上传。
+    // 客户端启用了 ImagePipeline 时会先整个读入内存处理，见 Post::process_image
+    pub fn from_file(path: impl Into<PathBuf>, file_name: impl Into<String>, mime: impl Into<String>) -> Self {
+        Self {
+            file_name: file_name.into(),
+            mime: mime.into(),
+            source: AttachmentSource::File(path.into()),
+        }
+    }
+
+    // 本地文件，文件名取自路径，MIME 类型由扩展名推断
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let file_name = path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+        let mime = mime_from_path(&path).to_string();
+        Self::from_file(path, file_name, mime)
+    }
+
+    // 异步读取流，length 已知时会作为该部分的长度发送。
+    // 客户端启用了 ImagePipeline（默认启用）时会先整个读入内存处理，
+    // 需要保持流式上传时请对该串调用 Post::process_image(false)
+    pub fn from_reader<R>(reader: R, length: Option<u64>, file_name: impl Into<String>, mime: impl Into<String>) -> Self
+        where R: AsyncRead + Send + 'static
+    {
+        Self {
+            file_name: file_name.into(),
+            mime: mime.into(),
+            source: AttachmentSource::Reader {
+                reader: Box::pin(reader),
+                length,
+            },
+        }
+    }
+
+    // 字节块流，例如转发其它 HTTP 请求的响应体。
+    // 与 from_reader 相同，经过 ImagePipeline 处理时会被整个读入内存
+    pub fn from_stream<S>(stream: S, length: Option<u64>, file_name: impl Into<String>, mime: impl Into<String>) -> Self
+        where S: Stream<Item = std::io::Result<Bytes>> + Send + 'static
+    {
+        Self::from_reader(StreamReader::new(stream), length, file_name, mime)
+    }
+
+    // 已知的数据长度，来自流且未给出长度时为 None
+    pub fn len_hint(&self) -> Option<u64> {
+        match &self.source {
+            AttachmentSource::Bytes(b) => Some(b.len() as u64),
+            AttachmentSource::File(_) => None,
+            AttachmentSource::Reader { length, .. } => *length,
+        }
+    }
+
+    // 内存和文件来源可以复制，流来源只能读取一次
+    pub fn try_clone(&self) -> Option<Self> {
+        let source = match &self.source {
+            AttachmentSource::Bytes(b) => AttachmentSource::Bytes(b.clone()),
+            AttachmentSource::File(p) => AttachmentSource::File(p.clone()),
+            AttachmentSource::Reader { .. } => return None,
+        };
+        Some(Self {
+            file_name: self.file_name.clone(),
+            mime: self.mime.clone(),
+            source,
+        })
+    }
+
+    // 将全部数据读入内存
+    pub async fn into_bytes(self) -> Result<Bytes> {
+        match self.source {
+            AttachmentSource::Bytes(b) => Ok(b),
+            AttachmentSource::File(p) => Ok(tokio::fs::read(p).await?.into()),
+            AttachmentSource::Reader { mut reader, length } => {
+                let mut buf = Vec::with_capacity(length.unwrap_or(0) as usize);
+                reader.read_to_end(&mut buf).await?;
+                Ok(buf.into())
+            }
+        }
+    }
+
+    // 将数据读入内存，但最多读取 limit 字节。已知长度或读取中超过 limit 时
+    // 返回 ImageError::ReadLimit，不会把过大的文件或无尽的流整个读入内存
+    pub async fn into_bytes_limited(self, limit: u64) -> Result<Bytes> {
+        let too_large = || Error::Image(ImageError::ReadLimit { max: limit });
+        if self.len_hint().is_some_and(|len| len > limit) {
+            return Err(too_large());
+        }
+        let capacity = self.len_hint().unwrap_or(0).min(limit) as usize;
+        let reader: Pin<Box<dyn AsyncRead + Send>> = match self.source {
+            AttachmentSource::Bytes(b) => return Ok(b),
+            AttachmentSource::File(p) => {
+                let file = tokio::fs::File::open(p).await?;
+                if file.metadata().await?.len() > limit {
+                    return Err(too_large());
+                }
+                Box::pin(file)
+            }
+            AttachmentSource::Reader { reader, .. } => reader,
+        };
+        let mut buf = Vec::with_capacity(capacity);
+        reader.take(limit.saturating_add(1)).read_to_end(&mut buf).await?;
+        if buf.len() as u64 > limit {
+            return Err(too_large());
+        }
+        Ok(buf.into())
+    }
+
+    // 读入内存后得到可复制的附图
+    pub async fn buffered(self) -> Result<Self> {
+        let file_name = self.file_name.clone();
+        let mime = self.mime.clone();
+        let data = self.into_bytes().await?;
+        Ok(Self::from_bytes(data, file_name, mime))
+    }
+
+    pub(crate) fn into_source(self) -> (String, String, AttachmentSource) {
+        (self.file_name, self.mime, self.source)
+    }
+}
+
+
+// 根据扩展名推断常见图片的 MIME 类型
+pub fn mime_from_path(path: &Path) -> &'static str {
+    let ext = path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => "application/octet-stream",
+    }
+}
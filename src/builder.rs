@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+
+use crate::{ApiClient, Result, RetryPolicy};
+use crate::cdnpath::CdnSelector;
+use crate::cooldown::{CooldownMode, CooldownTracker};
+use crate::imagecache::ImageCache;
+use crate::locate::LocationCache;
+use crate::imaging::ImagePipeline;
+use crate::ratelimit::RateLimiter;
+use crate::cookie::{CookieJar, UserCookie};
+use crate::transport::{RecordingTransport, ReqwestTransport, Transport};
+
+
+pub const DEFAULT_BASE_URL: &str = "https://api.nmb.best";
+pub const DEFAULT_POST_BASE_URL: &str = "https://www.nmbxd1.com";
+
+
+/// 用于构造 [`ApiClient`]，可配置API地址、发串地址、超时、User-Agent 等。
+#[derive(Debug, Default)]
+pub struct ApiClientBuilder {
+    auth_cookie: Option<UserCookie>,
+    feed_uuid: Option<String>,
+    base_url: Option<String>,
+    post_base_url: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    default_headers: HeaderMap,
+    client: Option<reqwest::Client>,
+    transport: Option<Box<dyn Transport>>,
+    record_dir: Option<PathBuf>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limiter: Option<Option<Arc<RateLimiter>>>,
+    cooldown: Option<Option<CooldownMode>>,
+    image_pipeline: Option<Option<ImagePipeline>>,
+    cdn_selector: Option<CdnSelector>,
+    image_cache_dir: Option<PathBuf>,
+    keep_tips: bool,
+    cookie_jar: Option<CookieJar>,
+}
+
+impl ApiClientBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 使用的饼干。同时设置了 cookie_jar 时，build() 会把它设为该共享 CookieJar 的
+    // 当前饼干，共享同一个 CookieJar 的其他客户端也随之切换
+    pub fn auth_cookie(mut self, cookie: UserCookie) -> Self {
+        self.auth_cookie = Some(cookie);
+        self
+    }
+
+    // 与其他客户端共享同一个 CookieJar，切换饼干时同时生效。
+    // 未调用 auth_cookie 时沿用 CookieJar 中现有的饼干，调用了则会替换它（见 auth_cookie）
+    pub fn cookie_jar(mut self, jar: CookieJar) -> Self {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    pub fn feed_uuid(mut self, uuid: impl Into<String>) -> Self {
+        self.feed_uuid = Some(uuid.into());
+        self
+    }
+
+    // API地址，默认为 https://api.nmb.best
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    // 发串/回复所用的网页版地址，默认为 https://www.nmbxd1.com
+    pub fn post_base_url(mut self, url: impl Into<String>) -> Self {
+        self.post_base_url = Some(url.into());
+        self
+    }
+
+    // 单次请求的总超时
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // 建立连接的超时
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    // 每个请求都会附带的请求头，可多次调用累加
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_headers.extend(headers);
+        self
+    }
+
+    // 使用预先配置好的 reqwest::Client，此时超时、User-Agent、默认请求头等设置不再生效
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    // 使用自定义传输层（如 MockTransport、ReplayTransport），优先于 client() 及其它网络设置
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Box::new(transport));
+        self
+    }
+
+    // 录制模式，把每一对请求/响应保存到目录中，之后可用 ReplayTransport 回放
+    pub fn record_fixtures(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.record_dir = Some(dir.into());
+        self
+    }
+
+    // 失败重试策略，默认见 RetryPolicy::default()
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    // 自定义限流器，传入同一个 Arc 可让多个客户端共享额度
+    pub fn rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(Some(limiter));
+        self
+    }
+
+    // 关闭客户端内置的限流
+    pub fn no_rate_limit(mut self) -> Self {
+        self.rate_limiter = Some(None);
+        self
+    }
+
+    // 发串冷却未结束时等待（默认）还是直接返回 Error::Cooldown
+    pub fn cooldown_mode(mut self, mode: CooldownMode) -> Self {
+        self.cooldown = Some(Some(mode));
+        self
+    }
+
+    // 不检查版块的发串冷却
+    pub fn no_cooldown(mut self) -> Self {
+        self.cooldown = Some(None);
+        self
+    }
+
+    // 附图上传前的处理方式，默认见 ImagePipeline::default()
+    pub fn image_pipeline(mut self, pipeline: ImagePipeline) -> Self {
+        self.image_pipeline = Some(Some(pipeline));
+        self
+    }
+
+    // 附图不做任何处理，原样上传
+    pub fn no_image_pipeline(mut self) -> Self {
+        self.image_pipeline = Some(None);
+        self
+    }
+
+    // 自定义CDN的选择方式、失败后的冷却时间和列表刷新间隔
+    pub fn cdn_selector(mut self, selector: CdnSelector) -> Self {
+        self.cdn_selector = Some(selector);
+        self
+    }
+
+    // 图片缓存目录，默认为系统临时目录下的 xdnmb-rs/images
+    pub fn image_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.image_cache_dir = Some(dir.into());
+        self
+    }
+
+    // 在串的回复列表中保留站点插入的 Tips，默认移到 Thread.tips
+    pub fn keep_tips(mut self, keep: bool) -> Self {
+        self.keep_tips = keep;
+        self
+    }
+
+    pub fn build(self) -> Result<ApiClient> {
+        let transport: Box<dyn Transport> = match (self.transport, self.client) {
+            (Some(transport), _) => transport,
+            (None, Some(client)) => Box::new(ReqwestTransport::new(client)),
+            (None, None) => {
+                let mut builder = reqwest::Client::builder()
+                    .default_headers(self.default_headers);
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                Box::new(ReqwestTransport::new(builder.build()?))
+            }
+        };
+        let transport: Arc<dyn Transport> = match self.record_dir {
+            Some(dir) => Arc::new(RecordingTransport::from_boxed(transport, dir)),
+            None => Arc::from(transport),
+        };
+        Ok(ApiClient {
+            cookie_jar: match self.cookie_jar {
+                Some(jar) => {
+                    // 显式设置的饼干优先，会改变共享 CookieJar 的当前饼干
+                    if self.auth_cookie.is_some() {
+                        jar.set(self.auth_cookie);
+                    }
+                    jar
+                }
+                None => CookieJar::new(self.auth_cookie),
+            },
+            feed_uuid: self.feed_uuid,
+            transport,
+            base_url: trim_base(self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL)),
+            post_base_url: trim_base(self.post_base_url.as_deref().unwrap_or(DEFAULT_POST_BASE_URL)),
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            rate_limiter: self.rate_limiter.unwrap_or_else(|| Some(Arc::new(RateLimiter::default()))),
+            cooldown: self.cooldown.unwrap_or(Some(CooldownMode::default()))
+                .map(|mode| Arc::new(CooldownTracker::new(mode))),
+            image_pipeline: self.image_pipeline.unwrap_or_else(|| Some(ImagePipeline::default())),
+            cdn: Arc::new(self.cdn_selector.unwrap_or_default()),
+            image_cache: Arc::new(self.image_cache_dir.map(ImageCache::new).unwrap_or_default()),
+            keep_tips: self.keep_tips,
+            locations: LocationCache::default(),
+        })
+    }
+}
+
+
+// 去掉末尾的斜杠，拼接路径时统一使用 "{base}/{path}"
+fn trim_base(url: &str) -> String {
+    url.trim_end_matches('/').to_string()
+}
@@ -1,3 +1,6 @@
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
 use serde::{Deserialize, Serialize};
 
 
@@ -9,3 +12,225 @@ pub struct CdnPath {
     pub rate: f32,
     pub url: String,
 }
+
+
+// 未获取CDN列表时使用的默认图片CDN
+pub const DEFAULT_CDN_URL: &str = "https://image.nmb.best/";
+
+
+/// 图片的两种尺寸：原图与缩略图
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageVariant {
+    Image,
+    Thumb,
+}
+
+impl ImageVariant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageVariant::Image => "image",
+            ImageVariant::Thumb => "thumb",
+        }
+    }
+}
+
+impl CdnPath {
+
+    // {cdn}/{image|thumb}/{img}{ext}，img 为空（无附图）时为 None
+    pub fn url_for(&self, variant: ImageVariant, img: &str, ext: &str) -> Option<String> {
+        if img.is_empty() {
+            return None;
+        }
+        Some(format!("{}/{}/{}{}", self.url.trim_end_matches('/'), variant.as_str(), img, ext))
+    }
+
+    pub fn image_url(&self, img: &str, ext: &str) -> Option<String> {
+        self.url_for(ImageVariant::Image, img, ext)
+    }
+
+    pub fn thumb_url(&self, img: &str, ext: &str) -> Option<String> {
+        self.url_for(ImageVariant::Thumb, img, ext)
+    }
+}
+
+
+/// 选择CDN的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CdnStrategy {
+    /// 按 rate 权重随机选择
+    #[default]
+    Weighted,
+    /// 选择测得延迟最低的，未测速时按权重选择
+    Fastest,
+}
+
+
+#[derive(Debug, Clone)]
+struct CdnHost {
+    cdn: CdnPath,
+    latency: Option<Duration>,
+    unhealthy_until: Option<Instant>,
+}
+
+impl CdnHost {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until.is_none_or(|t| t <= now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CdnState {
+    hosts: Vec<CdnHost>,
+    refreshed_at: Option<Instant>,
+}
+
+
+/// 图片CDN的选择器：按权重或延迟选择CDN，下载失败的CDN在一段时间内不再优先使用。
+/// ApiClient 的所有克隆共享同一个实例，CDN列表超过刷新间隔后会在下次使用时重新获取。
+#[derive(Debug)]
+pub struct CdnSelector {
+    strategy: CdnStrategy,
+    unhealthy_for: Duration,
+    refresh_interval: Duration,
+    state: RwLock<CdnState>,
+}
+
+impl Default for CdnSelector {
+    fn default() -> Self {
+        Self::new(CdnStrategy::default())
+    }
+}
+
+impl CdnSelector {
+
+    pub fn new(strategy: CdnStrategy) -> Self {
+        Self {
+            strategy,
+            unhealthy_for: Duration::from_secs(300),
+            refresh_interval: Duration::from_secs(3600),
+            state: RwLock::default(),
+        }
+    }
+
+    // 下载失败后该CDN被视为不可用的时长，默认5分钟
+    pub fn unhealthy_for(mut self, duration: Duration) -> Self {
+        self.unhealthy_for = duration;
+        self
+    }
+
+    // CDN列表的刷新间隔，默认1小时
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    pub fn strategy(&self) -> CdnStrategy {
+        self.strategy
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, CdnState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, CdnState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 更新CDN列表，已有CDN的测速结果和可用状态会保留
+    pub fn update(&self, list: CdnPathList) {
+        let mut state = self.write();
+        let hosts = list.into_iter()
+            .map(|cdn| match state.hosts.iter().find(|h| h.cdn.url == cdn.url) {
+                Some(old) => CdnHost { cdn, ..old.clone() },
+                None => CdnHost { cdn, latency: None, unhealthy_until: None },
+            })
+            .collect();
+        state.hosts = hosts;
+        state.refreshed_at = Some(Instant::now());
+    }
+
+    // 从未获取过或已超过刷新间隔
+    pub fn needs_refresh(&self) -> bool {
+        self.read().refreshed_at.is_none_or(|t| t.elapsed() >= self.refresh_interval)
+    }
+
+    pub fn list(&self) -> Option<CdnPathList> {
+        let state = self.read();
+        state.refreshed_at.map(|_| state.hosts.iter().map(|h| h.cdn.clone()).collect())
+    }
+
+    pub fn record_latency(&self, url: &str, latency: Duration) {
+        if let Some(host) = self.write().hosts.iter_mut().find(|h| h.cdn.url == url) {
+            host.latency = Some(latency);
+            host.unhealthy_until = None;
+        }
+    }
+
+    pub fn mark_unhealthy(&self, url: &str) {
+        let until = Instant::now() + self.unhealthy_for;
+        if let Some(host) = self.write().hosts.iter_mut().find(|h| h.cdn.url == url) {
+            host.unhealthy_until = Some(until);
+        }
+    }
+
+    pub fn mark_healthy(&self, url: &str) {
+        if let Some(host) = self.write().hosts.iter_mut().find(|h| h.cdn.url == url) {
+            host.unhealthy_until = None;
+        }
+    }
+
+    // 按当前策略选出的CDN，列表为空时为 None
+    pub fn select(&self) -> Option<CdnPath> {
+        self.candidates().into_iter().next()
+    }
+
+    // 下载时依次尝试的CDN：首选的CDN在前，其余可用的CDN其次，不可用的CDN排在最后
+    pub fn candidates(&self) -> Vec<CdnPath> {
+        let now = Instant::now();
+        let state = self.read();
+        let (mut healthy, mut unhealthy): (Vec<_>, Vec<_>) = state.hosts.iter()
+            .cloned()
+            .partition(|h| h.is_healthy(now));
+
+        let first = match self.strategy {
+            CdnStrategy::Fastest => healthy.iter()
+                .enumerate()
+                .filter_map(|(i, h)| Some((i, h.latency?)))
+                .min_by_key(|(_, latency)| *latency)
+                .map(|(i, _)| i)
+                .or_else(|| weighted_pick(&healthy)),
+            CdnStrategy::Weighted => weighted_pick(&healthy),
+        };
+        let mut ordered = Vec::with_capacity(state.hosts.len());
+        if let Some(i) = first {
+            ordered.push(healthy.remove(i).cdn);
+        }
+        healthy.sort_by(|a, b| {
+            let latency = |h: &CdnHost| h.latency.unwrap_or(Duration::MAX);
+            latency(a).cmp(&latency(b)).then(b.cdn.rate.total_cmp(&a.cdn.rate))
+        });
+        unhealthy.sort_by_key(|h| h.unhealthy_until);
+        ordered.extend(healthy.into_iter().map(|h| h.cdn));
+        ordered.extend(unhealthy.into_iter().map(|h| h.cdn));
+        ordered
+    }
+}
+
+// 按 rate 权重随机选出一个下标
+fn weighted_pick(hosts: &[CdnHost]) -> Option<usize> {
+    if hosts.is_empty() {
+        return None;
+    }
+    let total: f64 = hosts.iter().map(|h| h.cdn.rate.max(0.0) as f64).sum();
+    if total <= 0.0 {
+        return Some(0);
+    }
+    let mut point = fastrand::f64() * total;
+    for (i, host) in hosts.iter().enumerate() {
+        point -= host.cdn.rate.max(0.0) as f64;
+        if point < 0.0 {
+            return Some(i);
+        }
+    }
+    Some(hosts.len() - 1)
+}
@@ -1,3 +1,5 @@
+use std::sync::{Arc, RwLock};
+
 use serde::{Deserialize, Serialize};
 
 
@@ -20,3 +22,47 @@ impl UserCookie {
         }
     }
 }
+
+
+impl UserCookie {
+
+    // 请求头 Cookie 的值，读取与发串统一使用 userhash=<value> 的形式
+    pub fn header_value(&self) -> String {
+        format!("userhash={}", self.value)
+    }
+}
+
+
+/// 保存当前使用中的饼干，ApiClient 的所有克隆共享同一个 CookieJar，
+/// 运行中可随时切换饼干而无需重新构造客户端。
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    active: Arc<RwLock<Option<UserCookie>>>,
+}
+
+impl CookieJar {
+
+    pub fn new(cookie: Option<UserCookie>) -> Self {
+        Self {
+            active: Arc::new(RwLock::new(cookie)),
+        }
+    }
+
+    // 当前使用中的饼干
+    pub fn get(&self) -> Option<UserCookie> {
+        self.active.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // 切换饼干，返回之前使用的饼干
+    pub fn set(&self, cookie: Option<UserCookie>) -> Option<UserCookie> {
+        let mut active = self.active.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *active, cookie)
+    }
+
+    // 当前饼干对应的 Cookie 请求头，未设置饼干时为 None
+    pub fn header_value(&self) -> Option<String> {
+        self.active.read().unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(UserCookie::header_value)
+    }
+}
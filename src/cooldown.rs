@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::forum::ForumList;
+
+
+/// 发串冷却未结束时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CooldownMode {
+    /// 等待冷却结束后再发送
+    #[default]
+    Wait,
+    /// 立即返回 Error::Cooldown，不发送请求
+    Error,
+}
+
+
+/// 按版块和饼干记录上次发串时间，依据 Forum.interval 控制发串间隔。
+/// ApiClient 的所有克隆共享同一个实例。
+#[derive(Debug, Default)]
+pub struct CooldownTracker {
+    mode: CooldownMode,
+    state: Mutex<CooldownState>,
+}
+
+#[derive(Debug, Default)]
+struct CooldownState {
+    // 版块ID -> 发串间隔
+    intervals: Option<HashMap<i64, Duration>>,
+    // 串号 -> 所属版块ID，回复时用于查找冷却时间
+    thread_forums: HashMap<i64, i64>,
+    // (版块ID, 饼干) -> 下一次允许发串的时间
+    next_allowed: HashMap<(i64, String), Instant>,
+}
+
+impl CooldownTracker {
+
+    pub fn new(mode: CooldownMode) -> Self {
+        Self {
+            mode,
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn mode(&self) -> CooldownMode {
+        self.mode
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, CooldownState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 从版块列表中读取各版块的发串间隔
+    pub fn update_intervals(&self, forum_list: &ForumList) {
+        let intervals = forum_list.iter()
+            .flat_map(|group| group.forums.iter())
+            .filter_map(|forum| {
+                let secs = forum.interval.map(|n| n.into_inner()).unwrap_or(0);
+                Some((forum.fid.into_inner(), Duration::from_secs(u64::try_from(secs).ok()?)))
+            })
+            .collect();
+        self.state().intervals = Some(intervals);
+    }
+
+    pub fn has_intervals(&self) -> bool {
+        self.state().intervals.is_some()
+    }
+
+    pub fn interval(&self, fid: i64) -> Duration {
+        self.state().intervals.as_ref()
+            .and_then(|m| m.get(&fid).copied())
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub fn thread_forum(&self, tid: i64) -> Option<i64> {
+        self.state().thread_forums.get(&tid).copied()
+    }
+
+    pub fn set_thread_forum(&self, tid: i64, fid: i64) {
+        self.state().thread_forums.insert(tid, fid);
+    }
+
+    // 距离该版块、该饼干下一次可以发串还需要多久
+    pub fn remaining(&self, fid: i64, cookie: &str) -> Duration {
+        self.state().next_allowed.get(&(fid, cookie.to_string()))
+            .map(|t| t.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    // 预定一次发串：Wait 模式返回的预定中带有需要等待的时间；
+    // Error 模式下冷却未结束时返回剩余时间作为错误，且不做预定。
+    // 预定让并发的发串依次排队，发送成功后应调用 Reservation::commit，
+    // 未 commit 的预定在丢弃时（发送失败、调用方取消等）自动归还
+    pub fn reserve(self: &Arc<Self>, fid: i64, cookie: &str) -> Result<Reservation, Duration> {
+        let interval = self.interval(fid);
+        let now = Instant::now();
+        let mut state = self.state();
+        let key = (fid, cookie.to_string());
+        let previous = state.next_allowed.get(&key).copied();
+        let slot = match previous {
+            Some(t) if t > now => t,
+            _ => now,
+        };
+        if slot > now && self.mode == CooldownMode::Error {
+            return Err(slot - now);
+        }
+        let until = slot + interval;
+        state.next_allowed.insert(key, until);
+        Ok(Reservation {
+            tracker: self.clone(),
+            fid,
+            cookie: cookie.to_string(),
+            wait: slot - now,
+            previous,
+            until,
+            committed: false,
+        })
+    }
+
+    // 发串成功，从现在起重新计算冷却时间
+    fn commit(&self, reservation: &Reservation) {
+        let until = Instant::now() + self.interval(reservation.fid);
+        let mut state = self.state();
+        let next = state.next_allowed.entry((reservation.fid, reservation.cookie.clone())).or_insert(until);
+        *next = (*next).max(until);
+    }
+
+    // 发串失败，归还预定的冷却时间。之后已有其他预定时保持不变
+    fn release(&self, reservation: &Reservation) {
+        let mut state = self.state();
+        let key = (reservation.fid, reservation.cookie.clone());
+        if state.next_allowed.get(&key) != Some(&reservation.until) {
+            return;
+        }
+        match reservation.previous {
+            Some(previous) => state.next_allowed.insert(key, previous),
+            None => state.next_allowed.remove(&key),
+        };
+    }
+}
+
+
+/// CooldownTracker::reserve 得到的一次发串预定，未 commit 就被丢弃时自动归还
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    tracker: Arc<CooldownTracker>,
+    fid: i64,
+    cookie: String,
+    wait: Duration,
+    previous: Option<Instant>,
+    until: Instant,
+    committed: bool,
+}
+
+impl Reservation {
+
+    pub fn fid(&self) -> i64 {
+        self.fid
+    }
+
+    // 发串前需要等待的时间
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    // 发串成功，计入冷却
+    pub fn commit(mut self) {
+        self.tracker.commit(&self);
+        self.committed = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.tracker.release(self);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(mode: CooldownMode) -> Arc<CooldownTracker> {
+        let forum_list: ForumList = serde_json::from_value(serde_json::json!([{
+            "id": "1", "name": "综合", "sort": "1", "status": "n",
+            "forums": [{"id": "4", "msg": "", "name": "综合版1", "interval": "30"}],
+        }])).unwrap();
+        let tracker = Arc::new(CooldownTracker::new(mode));
+        tracker.update_intervals(&forum_list);
+        tracker
+    }
+
+    #[test]
+    fn released_reservation_does_not_block_next_post() {
+        let tracker = tracker(CooldownMode::Error);
+        let reservation = tracker.reserve(4, "cookie").unwrap();
+        assert!(reservation.wait().is_zero());
+        drop(reservation);
+        assert_eq!(tracker.remaining(4, "cookie"), Duration::ZERO);
+        assert!(tracker.reserve(4, "cookie").is_ok());
+    }
+
+    #[test]
+    fn committed_reservation_starts_interval() {
+        let tracker = tracker(CooldownMode::Error);
+        let reservation = tracker.reserve(4, "cookie").unwrap();
+        reservation.commit();
+        assert!(tracker.remaining(4, "cookie") > Duration::from_secs(29));
+        assert!(tracker.reserve(4, "cookie").is_err());
+        // 其他饼干、其他版块不受影响
+        assert!(tracker.reserve(4, "other").is_ok());
+        assert!(tracker.reserve(5, "cookie").is_ok());
+    }
+
+    #[test]
+    fn concurrent_reservations_queue_in_wait_mode() {
+        let tracker = tracker(CooldownMode::Wait);
+        let first = tracker.reserve(4, "cookie").unwrap();
+        let second = tracker.reserve(4, "cookie").unwrap();
+        assert!(second.wait() > Duration::from_secs(29));
+        // 之后已有其他预定，归还前一个不影响后一个的排队
+        drop(first);
+        assert!(tracker.remaining(4, "cookie") > Duration::from_secs(59));
+        second.commit();
+    }
+}
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::imaging::ImageError;
+use crate::post::{InvalidPost, PostRejection};
+
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+/// 本库所有接口统一使用的错误类型，满足 Send + Sync，可跨任务传递。
+#[derive(Error, Debug)]
+pub enum Error {
+
+    /// 网络传输层错误（连接失败、超时、读取响应体失败等）
+    #[error("transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// 服务器返回了非 2xx 的 HTTP 状态码
+    #[error("http status {status} from {url}")]
+    Status {
+        status: u16,
+        url: String,
+        body: String,
+    },
+
+    /// 响应体无法解析为预期的 JSON 结构
+    #[error("failed to decode json at `{path}`: {source}")]
+    Decode {
+        /// 出错字段的路径，如 `Replies[3].id`；整体不是合法 JSON 时为 `.`
+        path: String,
+        #[source]
+        source: serde_json::Error,
+        /// 原始响应体
+        body: String,
+    },
+
+    /// 站点通过 API 返回的错误信息
+    #[error("api error: {0}")]
+    Api(#[from] ApiError),
+
+    /// 发串内容未通过发送前的检查
+    #[error("invalid post: {0}")]
+    InvalidPost(#[from] InvalidPost),
+
+    /// 附图未通过上传前的检查或处理失败
+    #[error("image error: {0}")]
+    Image(#[from] ImageError),
+
+    /// 发串/回复被站点拒绝
+    #[error("post rejected: {0}")]
+    PostRejected(#[from] PostRejection),
+
+    /// 该版块的发串冷却尚未结束，未发送请求
+    #[error("cooldown in forum {fid}, retry after {}s", retry_after.as_secs_f64().ceil())]
+    Cooldown {
+        fid: i64,
+        retry_after: Duration,
+    },
+
+    /// 串或回复没有附图
+    #[error("post has no image")]
+    NoImage,
+
+    /// 读取本地文件失败
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// 模拟或回放传输层中找不到与请求对应的响应
+    #[error("no fixture for request `{0}`")]
+    FixtureMissing(String),
+
+    /// api/ref 和网页版引用页都无法确定该串号所属的主串
+    #[error("cannot determine the thread of post {0}")]
+    UnknownParent(i64),
+
+    /// 调用订阅相关接口前未设置订阅 uuid
+    #[error("feed uuid is not set")]
+    MissingFeedUuid,
+}
+
+
+/// 站点以裸字符串（如 `"该串不存在"`）或 `{"success":false,"error":...}` 形式返回的错误
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+
+    /// 串不存在或已被删除
+    #[error("thread not found: {0}")]
+    ThreadNotFound(String),
+
+    /// 版块不存在
+    #[error("forum not found: {0}")]
+    ForumNotFound(String),
+
+    /// 需要饼干才能访问
+    #[error("missing cookie: {0}")]
+    MissingCookie(String),
+
+    /// 页数超出范围
+    #[error("page out of range: {0}")]
+    PageOutOfRange(String),
+
+    /// 其他无法归类的错误信息
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ApiError {
+
+    // 根据站点返回的文字判断错误种类
+    pub fn from_message(message: &str) -> Self {
+        let message = message.to_string();
+        if message.contains("饼干") {
+            ApiError::MissingCookie(message)
+        } else if message.contains("版块") || message.contains("板块") {
+            ApiError::ForumNotFound(message)
+        } else if message.contains("页") {
+            ApiError::PageOutOfRange(message)
+        } else if message.contains("串") {
+            ApiError::ThreadNotFound(message)
+        } else {
+            ApiError::Other(message)
+        }
+    }
+
+    // 从API返回的JSON中识别错误，正常数据返回 None
+    pub fn from_payload(json: &Value) -> Option<Self> {
+        match json {
+            Value::String(s) => Some(Self::from_message(s)),
+            Value::Object(map) if map.get("success") == Some(&Value::Bool(false)) => {
+                let message = match map.get("error") {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(other) => other.to_string(),
+                    None => String::new(),
+                };
+                Some(Self::from_message(&message))
+            }
+            _ => None,
+        }
+    }
+
+    // 站点给出的原始错误信息
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::ThreadNotFound(m)
+            | ApiError::ForumNotFound(m)
+            | ApiError::MissingCookie(m)
+            | ApiError::PageOutOfRange(m)
+            | ApiError::Other(m) => m,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn classify_messages() {
+        let cases = [
+            ("该串不存在", ApiError::ThreadNotFound as fn(String) -> ApiError),
+            ("版块不存在", ApiError::ForumNotFound),
+            ("必须登入领取饼干后才可以访问", ApiError::MissingCookie),
+            ("页数超出范围", ApiError::PageOutOfRange),
+            ("服务器维护中", ApiError::Other),
+        ];
+        for (message, kind) in cases {
+            let error = ApiError::from_message(message);
+            assert_eq!(error, kind(message.to_string()), "{message}");
+            assert_eq!(error.message(), message);
+        }
+    }
+
+    #[test]
+    fn detect_error_payloads() {
+        assert_eq!(ApiError::from_payload(&json!("该串不存在")), Some(ApiError::ThreadNotFound("该串不存在".into())));
+        assert_eq!(
+            ApiError::from_payload(&json!({"success": false, "error": "版块不存在"})),
+            Some(ApiError::ForumNotFound("版块不存在".into())),
+        );
+        assert_eq!(ApiError::from_payload(&json!({"success": false})), Some(ApiError::Other(String::new())));
+        assert_eq!(ApiError::from_payload(&json!({"success": false, "error": 404})), Some(ApiError::Other("404".into())));
+    }
+
+    #[test]
+    fn normal_payloads_are_not_errors() {
+        assert_eq!(ApiError::from_payload(&json!([])), None);
+        assert_eq!(ApiError::from_payload(&json!({"id": "50000000", "content": "串"})), None);
+        assert_eq!(ApiError::from_payload(&json!({"success": true})), None);
+        assert_eq!(ApiError::from_payload(&json!(1)), None);
+    }
+}
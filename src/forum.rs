@@ -17,8 +17,11 @@ use serde::de::DeserializeOwned;
 pub type ForumList = Vec<ForumGroup>;
 
 
+#[allow(clippy::upper_case_acronyms)]
 type NUM = SNum;
+#[allow(clippy::upper_case_acronyms)]
 type BOOL = SNBool;
+#[allow(clippy::upper_case_acronyms)]
 type TIME = String;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -110,6 +113,8 @@ pub struct Thread {
 
     /// 主串所属的版块ID。
     pub fid: Option<NUM>,
+    /// 回复所属主串的串号，主串本身为0（仅部分接口返回，如 api/getLastPost）
+    pub resto: Option<NUM>,
     /// 该主题串的回复数量
     #[serde(alias = "ReplyCount")]
     pub reply_count: Option<NUM>,
@@ -148,6 +153,10 @@ pub struct Thread {
     #[serde(default, deserialize_with = "deserialize_string_wrapped_json")]
     pub recent_replies: Option<Vec<NUM>>, // 有此字段则表示该帖子来源为订阅列表
 
+    /// 从回复列表中移出的 Tips 公告（见 Thread::strip_tips）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tips: Option<TipsNotice>,
+
     // pub po: Option<String>,
     // pub user_id: Option<NUM>,
     // pub file_id: Option<NUM>,
@@ -157,10 +166,86 @@ pub struct Thread {
 
 pub type ThreadReply = Thread;
 
+/// 串页（api/thread、api/po）每页的回复数量
+pub const REPLIES_PER_PAGE: usize = 19;
+
+/// 回复数量对应的串页数，没有回复时也有 1 页（只有主串）
+pub fn page_count(reply_count: usize) -> usize {
+    reply_count.div_ceil(REPLIES_PER_PAGE).max(1)
+}
+
+/// 站点插入回复列表中的“Tips”广告/公告的串号
+pub const TIPS_ID: i64 = 9999999;
+
+impl Thread {
+
+    /// 是否为站点插入回复列表的“Tips”，而不是真正的回复
+    pub fn is_tips(&self) -> bool {
+        *self.tid == TIPS_ID || self.user_hash == "Tips"
+    }
+
+    /// 把回复列表中的 Tips 移到 tips 字段，返回移出的 Tips
+    pub fn strip_tips(&mut self) -> Option<&TipsNotice> {
+        if let Some(replies) = self.replies.as_mut()
+            && let Some(index) = replies.iter().position(|reply| reply.is_tips())
+        {
+            let tips = replies.remove(index);
+            replies.retain(|reply| !reply.is_tips());
+            self.tips = Some(tips.into());
+        }
+        self.tips.as_ref()
+    }
+
+    /// 根据回复数量算出的串页数，至少为 1；没有 reply_count 字段时为 None
+    pub fn page_count(&self) -> Option<usize> {
+        self.reply_count.map(|n| page_count(n.into_inner().max(0) as usize))
+    }
+
+    /// 本页中真正的回复数量（不含 Tips）
+    pub fn real_reply_count(&self) -> usize {
+        self.replies.as_ref().map_or(0, |replies| replies.iter().filter(|r| !r.is_tips()).count())
+    }
 
+    /// 是否带有附图
+    pub fn has_image(&self) -> bool {
+        !self.img.is_empty()
+    }
+}
 
 
-/// 代表一条回复（跟帖）。
+/// 站点插入串回复列表中的公告/广告（串号 9999999，饼干为 "Tips"），并非真正的回复。
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TipsNotice {
+    pub id: i64,
+    pub user_hash: String,
+    pub now: TIME,
+    pub title: Option<String>,
+    pub name: Option<String>,
+    /// 公告正文，含HTML
+    pub content: String,
+    pub img: String,
+    pub ext: String,
+}
+
+impl From<Thread> for TipsNotice {
+    fn from(thread: Thread) -> Self {
+        Self {
+            id: thread.tid.into_inner(),
+            user_hash: thread.user_hash,
+            now: thread.now,
+            title: thread.title,
+            name: thread.name,
+            content: thread.content,
+            img: thread.img,
+            ext: thread.ext,
+        }
+    }
+}
+
+
+
+
+// 代表一条回复（跟帖）。
 
 // #[derive(Serialize, Deserialize, Debug, Clone)]
 // pub struct ThreadReply {
@@ -332,3 +417,58 @@ impl<'de> Deserialize<'de> for SNBool {
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn num(value: Value) -> Result<i64, serde_json::Error> {
+        serde_json::from_value::<SNum>(value).map(SNum::into_inner)
+    }
+
+    fn boolean(value: Value) -> Result<bool, serde_json::Error> {
+        serde_json::from_value::<SNBool>(value).map(SNBool::into_inner)
+    }
+
+    #[test]
+    fn snum_accepts_numbers_and_strings() {
+        assert_eq!(num(json!(50000000)).unwrap(), 50000000);
+        assert_eq!(num(json!("50000000")).unwrap(), 50000000);
+        assert_eq!(num(json!("-1")).unwrap(), -1);
+        assert_eq!(num(json!("")).unwrap(), 0);
+        assert!(num(json!("abc")).is_err());
+        assert!(num(json!(1.5)).is_err());
+        assert!(num(json!(null)).is_err());
+    }
+
+    #[test]
+    fn snbool_accepts_bools_numbers_and_strings() {
+        for (value, expected) in [
+            (json!(true), true), (json!(false), false),
+            (json!(1), true), (json!(0), false),
+            (json!("1"), true), (json!("0"), false), (json!(""), false),
+        ] {
+            assert_eq!(boolean(value.clone()).unwrap(), expected, "{value}");
+        }
+        assert!(boolean(json!(2)).is_err());
+        assert!(boolean(json!("2")).is_err());
+        assert!(boolean(json!("yes")).is_err());
+    }
+
+    #[test]
+    fn recent_replies_wrapped_in_string() {
+        let thread = |recent: Value| -> Thread {
+            serde_json::from_value(json!({
+                "id": 1, "user_hash": "a", "now": "", "content": "", "img": "", "ext": "",
+                "recent_replies": recent,
+            })).unwrap()
+        };
+        let ids = |t: Thread| t.recent_replies.map(|v| v.iter().map(|n| n.into_inner()).collect::<Vec<_>>());
+        assert_eq!(ids(thread(json!("[2,3]"))), Some(vec![2, 3]));
+        assert_eq!(ids(thread(json!([2, 3]))), Some(vec![2, 3]));
+        assert_eq!(ids(thread(json!(""))), None);
+        assert_eq!(ids(thread(json!(null))), None);
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+use crate::cdnpath::ImageVariant;
+
+
+/// 批量下载串内附图的选项
+#[derive(Debug, Clone)]
+pub struct HarvestOptions {
+    po_only: bool,
+    variant: ImageVariant,
+    concurrency: usize,
+    manifest: Option<PathBuf>,
+}
+
+impl Default for HarvestOptions {
+    fn default() -> Self {
+        Self {
+            po_only: false,
+            variant: ImageVariant::Image,
+            concurrency: 4,
+            manifest: None,
+        }
+    }
+}
+
+impl HarvestOptions {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 只下载Po的附图（通过 api/po 获取），默认下载全部
+    pub fn po_only(mut self, po_only: bool) -> Self {
+        self.po_only = po_only;
+        self
+    }
+
+    // 下载原图还是缩略图，默认原图
+    pub fn variant(mut self, variant: ImageVariant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    // 同时进行的下载数量，默认 4
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // 下载完成后把清单以 JSON 写入该路径
+    pub fn manifest(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest = Some(path.into());
+        self
+    }
+
+    pub fn is_po_only(&self) -> bool {
+        self.po_only
+    }
+
+    pub fn image_variant(&self) -> ImageVariant {
+        self.variant
+    }
+
+    pub fn concurrency_limit(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn manifest_path(&self) -> Option<&Path> {
+        self.manifest.as_deref()
+    }
+}
+
+
+/// 每张附图下载结束（成功或失败）时回调的进度
+#[derive(Debug)]
+pub struct HarvestProgress<'a> {
+    /// 已结束的数量
+    pub completed: usize,
+    /// 附图总数
+    pub total: usize,
+    /// 该附图所在回复的串号
+    pub post_id: i64,
+    /// 下载到的文件或错误
+    pub result: &'a Result<PathBuf>,
+}
+
+
+/// 串号与下载到的文件的对应清单
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarvestManifest {
+    pub tid: i64,
+    /// "image" 或 "thumb"
+    pub variant: String,
+    pub entries: Vec<ManifestEntry>,
+    pub failures: Vec<HarvestFailure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub post_id: i64,
+    pub img: String,
+    pub ext: String,
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarvestFailure {
+    pub post_id: i64,
+    pub img: String,
+    pub ext: String,
+    pub error: String,
+}
+
+impl HarvestManifest {
+
+    pub(crate) fn new(tid: i64, variant: ImageVariant) -> Self {
+        Self {
+            tid,
+            variant: variant.as_str().to_string(),
+            entries: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    // 以 JSON 格式写入文件
+    pub async fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() && !parent.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+}
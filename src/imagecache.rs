@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWrite;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::Result;
+use crate::cdnpath::ImageVariant;
+use crate::transport::{Response, StreamSink};
+
+
+/// 图片的本地缓存，按内容的 SHA-256 存放：
+/// - `objects/{哈希前两位}/{哈希}{ext}` 图片文件，内容相同的图片只存一份
+/// - `index/{image|thumb}/{img}{ext}.ref` 记录 img 对应的图片文件
+/// - `partial/{image|thumb}/{img}{ext}.part` 未下载完的部分，用于断点续传
+#[derive(Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join("xdnmb-rs").join("images"))
+    }
+}
+
+impl ImageCache {
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            locks: Mutex::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // 缓存中的相对路径，img 形如 "2022-06-18/62acedc59ef24"，不允许出现 ".."
+    fn key(variant: ImageVariant, img: &str, ext: &str) -> Option<String> {
+        let key = format!("{}/{}{}", variant.as_str(), img.trim_start_matches('/'), ext);
+        match key.split(['/', '\\']).any(|seg| seg == ".." || seg.is_empty()) {
+            true => None,
+            false => Some(key),
+        }
+    }
+
+    fn index_path(&self, key: &str) -> PathBuf {
+        self.dir.join("index").join(format!("{key}.ref"))
+    }
+
+    pub(crate) fn partial_path(&self, variant: ImageVariant, img: &str, ext: &str) -> Option<PathBuf> {
+        Self::key(variant, img, ext).map(|key| self.dir.join("partial").join(format!("{key}.part")))
+    }
+
+    // 已缓存的图片文件路径，未缓存时为 None
+    pub async fn lookup(&self, variant: ImageVariant, img: &str, ext: &str) -> Result<Option<PathBuf>> {
+        let Some(key) = Self::key(variant, img, ext) else {
+            return Ok(None);
+        };
+        let object = match tokio::fs::read_to_string(self.index_path(&key)).await {
+            Ok(object) => self.dir.join(object.trim()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match tokio::fs::try_exists(&object).await? {
+            true => Ok(Some(object)),
+            false => Ok(None),
+        }
+    }
+
+    fn locks(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<tokio::sync::Mutex<()>>>> {
+        self.locks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 同一张图片同时只允许一个下载任务写入
+    pub(crate) async fn lock(&self, variant: ImageVariant, img: &str, ext: &str) -> DownloadGuard<'_> {
+        let key = format!("{}/{}{}", variant.as_str(), img, ext);
+        let lock = self.locks().entry(key.clone()).or_default().clone();
+        DownloadGuard {
+            cache: self,
+            key,
+            guard: Some(lock.lock_owned().await),
+        }
+    }
+
+    // 把下载完成的文件按内容哈希移入缓存，并记录 img 与其对应关系
+    pub(crate) async fn commit(&self, variant: ImageVariant, img: &str, ext: &str, partial: &Path) -> Result<PathBuf> {
+        let data = tokio::fs::read(partial).await?;
+        let hash = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let relative = format!("objects/{}/{}{}", &hash[..2], hash, ext);
+        let object = self.dir.join(&relative);
+        if let Some(parent) = object.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        match tokio::fs::try_exists(&object).await? {
+            true => tokio::fs::remove_file(partial).await?,
+            false => tokio::fs::rename(partial, &object).await?,
+        }
+
+        if let Some(key) = Self::key(variant, img, ext) {
+            let index = self.index_path(&key);
+            if let Some(parent) = index.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(index, relative).await?;
+        }
+        Ok(object)
+    }
+}
+
+
+// 持有某张图片的下载锁，释放时若没有其他任务在等待同一张图片，就从锁表中删除该项
+pub(crate) struct DownloadGuard<'a> {
+    cache: &'a ImageCache,
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for DownloadGuard<'_> {
+    fn drop(&mut self) {
+        // 先释放锁，此时锁表之外仍持有该锁的只有等待中的任务
+        drop(self.guard.take());
+        let mut locks = self.cache.locks();
+        if locks.get(&self.key).is_some_and(|lock| Arc::strong_count(lock) == 1) {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+
+// 未下载完的图片文件，每次请求前按当前长度续传。
+// 服务器忽略 Range 返回 200 时先清空已有内容，206 的起始位置与当前长度不符时报错
+#[derive(Debug)]
+pub(crate) struct PartialFile {
+    file: tokio::fs::File,
+    offset: u64,
+}
+
+impl PartialFile {
+
+    pub(crate) async fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self { file, offset: 0 })
+    }
+
+    // 已下载的长度，即下一次请求的 Range 起点
+    pub(crate) async fn offset(&mut self) -> io::Result<u64> {
+        self.offset = self.file.metadata().await?.len();
+        Ok(self.offset)
+    }
+
+    pub(crate) async fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0).await?;
+        self.offset = 0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StreamSink for PartialFile {
+    async fn start(&mut self, head: &Response) -> io::Result<()> {
+        if head.status != 206 {
+            return self.truncate().await;
+        }
+        match head.header("content-range").and_then(content_range) {
+            Some((Some(start), _)) if start == self.offset => Ok(()),
+            range => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected content-range {range:?}, expected start {}", self.offset),
+            )),
+        }
+    }
+}
+
+impl AsyncWrite for PartialFile {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+
+// 解析 Content-Range，"bytes 100-199/200" 为 (Some(100), Some(200))，
+// "bytes */200"（416 响应）为 (None, Some(200))，总长未知的 "*" 为 None
+pub(crate) fn content_range(value: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes")?.trim().split_once('/')?;
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.trim().parse().ok()?),
+    };
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start, total))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_content_range() {
+        assert_eq!(content_range("bytes 100-199/200"), Some((Some(100), Some(200))));
+        assert_eq!(content_range("bytes 0-99/*"), Some((Some(0), None)));
+        assert_eq!(content_range("bytes */200"), Some((None, Some(200))));
+        assert_eq!(content_range("bytes abc/200"), None);
+        assert_eq!(content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn reject_paths_outside_cache() {
+        assert!(ImageCache::key(ImageVariant::Image, "2022-06-18/62acedc59ef24", ".png").is_some());
+        assert!(ImageCache::key(ImageVariant::Image, "../../etc/passwd", "").is_none());
+        assert!(ImageCache::key(ImageVariant::Thumb, "a//b", ".jpg").is_none());
+    }
+
+    #[tokio::test]
+    async fn locks_are_removed_after_use() {
+        use std::time::Duration;
+
+        let cache = ImageCache::new(std::env::temp_dir().join("xdnmb-rs-lock-test"));
+        let first = cache.lock(ImageVariant::Image, "a", ".png").await;
+        // 放弃等待的任务不会留下锁
+        assert!(tokio::time::timeout(Duration::from_millis(10), cache.lock(ImageVariant::Image, "a", ".png")).await.is_err());
+        drop(first);
+        assert!(cache.locks().is_empty());
+
+        // 释放时还有任务在等待，由最后一个任务删除
+        let first = cache.lock(ImageVariant::Image, "a", ".png").await;
+        let (_, second) = tokio::join!(
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(first);
+            },
+            cache.lock(ImageVariant::Image, "a", ".png"),
+        );
+        assert_eq!(cache.locks().len(), 1);
+        drop(second);
+        assert!(cache.locks().is_empty());
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
+use image::{DynamicImage, ImageDecoder, ImageReader, metadata::Orientation};
+use img_parts::{ImageEXIF, jpeg::Jpeg, png::Png, webp::WebP};
+use thiserror::Error;
+
+use crate::attachment::Attachment;
+
+
+// 处理前最多读入 max_bytes 的多少倍。原图可以比站点限制大，缩小后再上传，
+// 但不应把任意大的文件或无尽的流读入内存
+const READ_LIMIT_FACTOR: u64 = 16;
+
+/// 根据文件头识别出的图片格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Bmp,
+}
+
+impl ImageFormat {
+
+    // 根据文件头的魔数判断图片格式，无法识别时为 None
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(ImageFormat::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(ImageFormat::WebP),
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    // 根据扩展名（可带开头的点，不区分大小写）判断图片格式
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.trim_start_matches('.').to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "gif" => Some(ImageFormat::Gif),
+            "webp" => Some(ImageFormat::WebP),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    // 扩展名，带开头的点，与 Thread.ext 的格式相同
+    pub fn ext(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => ".jpg",
+            ImageFormat::Png => ".png",
+            ImageFormat::Gif => ".gif",
+            ImageFormat::WebP => ".webp",
+            ImageFormat::Bmp => ".bmp",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::WebP => "image/webp",
+            ImageFormat::Bmp => "image/bmp",
+        }
+    }
+}
+
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+
+    /// 文件头不是可识别的图片格式，或该格式不允许上传
+    #[error("unsupported image format: {0}")]
+    Unsupported(String),
+
+    /// 图片超过大小限制，且无法压缩（如GIF动图）或压缩后仍然过大
+    #[error("image is {size} bytes, larger than the {max} bytes limit")]
+    TooLarge {
+        size: u64,
+        max: u64,
+    },
+
+    /// 原图超过处理前允许读入内存的大小（max_bytes 的若干倍）
+    #[error("image is larger than the {max} bytes read limit")]
+    ReadLimit {
+        max: u64,
+    },
+
+    /// 图片的真实格式与扩展名不符
+    #[error("image data is {actual}, expected {expected}")]
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+
+    /// 图片数据损坏或无法解码
+    #[error("failed to process image: {0}")]
+    Decode(String),
+}
+
+impl From<image::ImageError> for ImageError {
+    fn from(e: image::ImageError) -> Self {
+        ImageError::Decode(e.to_string())
+    }
+}
+
+
+/// 附图上传前的处理：识别真实格式、拒绝不支持的格式、去除 EXIF/GPS 等元数据，
+/// 超过站点大小限制时缩小尺寸并重新压缩。
+#[derive(Debug, Clone)]
+pub struct ImagePipeline {
+    max_bytes: u64,
+    allowed: Vec<ImageFormat>,
+    strip_metadata: bool,
+    downscale: bool,
+    jpeg_quality: u8,
+}
+
+impl Default for ImagePipeline {
+    fn default() -> Self {
+        Self {
+            max_bytes: 2 * 1024 * 1024,
+            allowed: vec![ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Gif, ImageFormat::WebP],
+            strip_metadata: true,
+            downscale: true,
+            jpeg_quality: 85,
+        }
+    }
+}
+
+impl ImagePipeline {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 站点允许的最大文件大小（字节），默认 2MiB
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    // 允许上传的格式，默认 JPEG、PNG、GIF、WebP
+    pub fn allowed_formats(mut self, formats: impl IntoIterator<Item = ImageFormat>) -> Self {
+        self.allowed = formats.into_iter().collect();
+        self
+    }
+
+    // 是否去除 EXIF 等元数据，默认去除
+    pub fn strip_metadata(mut self, strip: bool) -> Self {
+        self.strip_metadata = strip;
+        self
+    }
+
+    // 超过大小限制时是否缩小并重新压缩，关闭后直接返回 ImageError::TooLarge
+    pub fn downscale(mut self, downscale: bool) -> Self {
+        self.downscale = downscale;
+        self
+    }
+
+    // 重新压缩为 JPEG 时的初始质量（1~100）
+    pub fn jpeg_quality(mut self, quality: u8) -> Self {
+        self.jpeg_quality = quality.clamp(1, 100);
+        self
+    }
+
+    // 处理内存中的图片，返回处理后的数据及其真实格式
+    pub fn process(&self, data: Bytes) -> Result<(Bytes, ImageFormat), ImageError> {
+        let format = ImageFormat::sniff(&data)
+            .ok_or_else(|| ImageError::Unsupported("unknown".to_string()))?;
+        if !self.allowed.contains(&format) {
+            return Err(ImageError::Unsupported(format.mime().to_string()));
+        }
+
+        let mut data = data;
+        if self.strip_metadata {
+            // 带旋转信息的照片去掉 EXIF 后方向会出错，需先按 EXIF 旋转再重新编码
+            match orientation(&data, format) {
+                Some(o) if o != Orientation::NoTransforms => {
+                    let mut image = decode(&data)?;
+                    image.apply_orientation(o);
+                    return self.fit(image, format, self.jpeg_quality);
+                }
+                _ => data = strip_metadata(data, format)?,
+            }
+        }
+
+        if (data.len() as u64) <= self.max_bytes {
+            return Ok((data, format));
+        }
+        if !self.downscale || format == ImageFormat::Gif {
+            return Err(ImageError::TooLarge { size: data.len() as u64, max: self.max_bytes });
+        }
+        let image = decode(&data)?;
+        self.fit(image, format, self.jpeg_quality)
+    }
+
+    // 处理附图，文件名的扩展名和 MIME 类型会随真实格式更新
+    pub async fn process_attachment(&self, attachment: Attachment) -> crate::Result<Attachment> {
+        let file_name = attachment.file_name.clone();
+        let data = attachment.into_bytes_limited(self.max_bytes.saturating_mul(READ_LIMIT_FACTOR)).await?;
+        let pipeline = self.clone();
+        let (data, format) = tokio::task::spawn_blocking(move || pipeline.process(data))
+            .await
+            .map_err(|e| ImageError::Decode(e.to_string()))??;
+        let stem = match file_name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => file_name.as_str(),
+        };
+        Ok(Attachment::from_bytes(data, format!("{}{}", stem, format.ext()), format.mime()))
+    }
+
+    // 重新编码（不含元数据），仍超过大小限制时逐步缩小尺寸和降低质量
+    fn fit(&self, mut image: DynamicImage, format: ImageFormat, mut quality: u8) -> Result<(Bytes, ImageFormat), ImageError> {
+        // PNG/WebP 先尝试无损的 PNG，仍过大且没有透明通道时改为 JPEG
+        let mut target = match format {
+            ImageFormat::Png | ImageFormat::WebP => ImageFormat::Png,
+            ImageFormat::Gif => ImageFormat::Gif,
+            ImageFormat::Jpeg | ImageFormat::Bmp => ImageFormat::Jpeg,
+        };
+        for _ in 0..8 {
+            let data = encode(&image, target, quality)?;
+            let size = data.len() as u64;
+            if size <= self.max_bytes {
+                return Ok((data, target));
+            }
+            if target == ImageFormat::Png && !image.color().has_alpha() {
+                target = ImageFormat::Jpeg;
+                continue;
+            }
+            if !self.downscale {
+                return Err(ImageError::TooLarge { size, max: self.max_bytes });
+            }
+            // 面积与文件大小大致成正比，按比例缩小边长并留一些余量
+            let scale = ((self.max_bytes as f64 / size as f64).sqrt() * 0.9).clamp(0.3, 0.9);
+            let width = ((image.width() as f64 * scale) as u32).max(1);
+            let height = ((image.height() as f64 * scale) as u32).max(1);
+            image = image.resize(width, height, image::imageops::FilterType::Lanczos3);
+            quality = quality.saturating_sub(5).max(self.jpeg_quality.min(60));
+        }
+        let size = encode(&image, target, quality)?.len() as u64;
+        Err(ImageError::TooLarge { size, max: self.max_bytes })
+    }
+}
+
+
+fn decode(data: &[u8]) -> Result<DynamicImage, ImageError> {
+    Ok(ImageReader::new(Cursor::new(data)).with_guessed_format()
+        .map_err(|e| ImageError::Decode(e.to_string()))?
+        .decode()?)
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Bytes, ImageError> {
+    let mut buf = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, quality);
+            image.to_rgb8().write_with_encoder(encoder)?;
+        }
+        ImageFormat::Png => image.write_to(&mut buf, image::ImageFormat::Png)?,
+        ImageFormat::Gif => image.write_to(&mut buf, image::ImageFormat::Gif)?,
+        ImageFormat::WebP => image.write_to(&mut buf, image::ImageFormat::WebP)?,
+        ImageFormat::Bmp => image.write_to(&mut buf, image::ImageFormat::Bmp)?,
+    }
+    Ok(buf.into_inner().into())
+}
+
+// EXIF 中记录的拍摄方向
+fn orientation(data: &[u8], format: ImageFormat) -> Option<Orientation> {
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP) {
+        return None;
+    }
+    let mut decoder = ImageReader::new(Cursor::new(data)).with_guessed_format().ok()?
+        .into_decoder().ok()?;
+    decoder.orientation().ok()
+}
+
+// 不重新编码，直接去掉文件中的 EXIF、XMP 等元数据块
+fn strip_metadata(data: Bytes, format: ImageFormat) -> Result<Bytes, ImageError> {
+    let decode_err = |e: img_parts::Error| ImageError::Decode(e.to_string());
+    match format {
+        ImageFormat::Jpeg => {
+            let mut jpeg = Jpeg::from_bytes(data).map_err(decode_err)?;
+            // APP1 存放 EXIF 和 XMP，APP13 存放 IPTC
+            jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP1);
+            jpeg.remove_segments_by_marker(img_parts::jpeg::markers::APP13);
+            Ok(jpeg.encoder().bytes())
+        }
+        ImageFormat::Png => {
+            let mut png = Png::from_bytes(data).map_err(decode_err)?;
+            for kind in [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"] {
+                png.remove_chunks_by_type(kind);
+            }
+            Ok(png.encoder().bytes())
+        }
+        ImageFormat::WebP => {
+            let mut webp = WebP::from_bytes(data).map_err(decode_err)?;
+            webp.set_exif(None);
+            webp.remove_chunks_by_id(*b"XMP ");
+            Ok(webp.encoder().bytes())
+        }
+        ImageFormat::Gif | ImageFormat::Bmp => Ok(data),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_matches_extension() {
+        let cases: [(&[u8], &str); 4] = [
+            (&[0xFF, 0xD8, 0xFF, 0xE0], ".jpg"),
+            (&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A], ".png"),
+            (b"GIF89a", ".gif"),
+            (b"RIFF\0\0\0\0WEBPVP8 ", ".webp"),
+        ];
+        for (data, ext) in cases {
+            assert_eq!(ImageFormat::sniff(data), ImageFormat::from_ext(ext), "{ext}");
+            assert_eq!(ImageFormat::sniff(data).unwrap().ext(), ext);
+        }
+        assert_eq!(ImageFormat::from_ext("JPEG"), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::sniff(b"<html>"), None);
+    }
+
+    #[test]
+    fn low_quality_is_not_raised_when_downscaling() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| {
+            image::Rgb([(x * 7 % 256) as u8, (y * 13 % 256) as u8, ((x ^ y) % 256) as u8])
+        }));
+        let low = encode(&image, ImageFormat::Jpeg, 20).unwrap().len() as u64;
+        // 限制略小于质量 20 的大小，只需稍微缩小即可满足，质量不应被提高到 60
+        let pipeline = ImagePipeline::new().jpeg_quality(20).max_bytes(low - 1);
+        let (data, format) = pipeline.fit(image.clone(), ImageFormat::Jpeg, 20).unwrap();
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert!(data.len() as u64 <= low);
+        // 质量保持在 20 以下时缩小一次就够了，被提高到 60 则需要缩小多次
+        assert!(decode(&data).unwrap().width() >= 200);
+    }
+
+    // 不易压缩的 RGB 图片，没有透明通道
+    fn noise(width: u32, height: u32) -> DynamicImage {
+        let mut seed = 1u32;
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |_, _| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let [r, g, b, _] = seed.to_be_bytes();
+            image::Rgb([r, g, b])
+        }))
+    }
+
+    #[test]
+    fn exif_is_stripped_from_jpeg() {
+        let data = encode(&noise(32, 32), ImageFormat::Jpeg, 85).unwrap();
+        let mut jpeg = Jpeg::from_bytes(data).unwrap();
+        // 只有 TIFF 头和空 IFD 的 EXIF，不含拍摄方向，不需要重新编码
+        jpeg.set_exif(Some(Bytes::from_static(b"II*\0\x08\0\0\0\0\0\0\0\0\0")));
+        let data = jpeg.encoder().bytes();
+        assert!(Jpeg::from_bytes(data.clone()).unwrap().exif().is_some());
+
+        let (processed, format) = ImagePipeline::new().process(data).unwrap();
+        assert_eq!(format, ImageFormat::Jpeg);
+        let jpeg = Jpeg::from_bytes(processed).unwrap();
+        assert!(jpeg.exif().is_none());
+        assert!(jpeg.segments_by_marker(img_parts::jpeg::markers::APP1).next().is_none());
+    }
+
+    #[test]
+    fn oversized_png_is_downscaled_to_jpeg() {
+        let data = encode(&noise(256, 256), ImageFormat::Png, 85).unwrap();
+        let max = 40 * 1024;
+        assert!(data.len() as u64 > max);
+
+        let (processed, format) = ImagePipeline::new().max_bytes(max).process(data).unwrap();
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(ImageFormat::sniff(&processed), Some(ImageFormat::Jpeg));
+        assert!(processed.len() as u64 <= max);
+        assert!(decode(&processed).unwrap().width() < 256);
+    }
+
+    #[tokio::test]
+    async fn oversized_input_is_rejected_before_reading() {
+        let pipeline = ImagePipeline::new().max_bytes(1024);
+        let limit = 1024 * READ_LIMIT_FACTOR;
+
+        // 已知长度超过上限时不读取数据
+        let reader = tokio::io::repeat(0);
+        let attachment = Attachment::from_reader(reader, Some(limit + 1), "a.png", "image/png");
+        assert!(matches!(
+            pipeline.process_attachment(attachment).await,
+            Err(crate::Error::Image(ImageError::ReadLimit { max })) if max == limit,
+        ));
+
+        // 未给出长度的无尽流只读到上限为止
+        let attachment = Attachment::from_reader(tokio::io::repeat(0), None, "a.png", "image/png");
+        assert!(matches!(
+            pipeline.process_attachment(attachment).await,
+            Err(crate::Error::Image(ImageError::ReadLimit { .. })),
+        ));
+    }
+}
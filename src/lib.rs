@@ -1,60 +1,516 @@
-use reqwest::multipart;
+use serde::de::DeserializeOwned;
 use serde_json as json;
-use tokio;
-use std::{collections::HashMap, error::Error, fmt::Display};
+use futures::Stream;
+use std::{collections::HashMap, fmt::Display, path::PathBuf, sync::Arc, time::{Duration, Instant}};
 
 
 pub mod forum; use forum::{ ForumList, ThreadList, TimelineList, ThreadReply};
-pub mod cdnpath; use cdnpath::CdnPathList;
-pub mod cookie; use cookie::UserCookie;
+pub mod cdnpath; use cdnpath::{CdnPath, CdnPathList, CdnSelector, ImageVariant};
+pub mod cookie; use cookie::{CookieJar, UserCookie};
+pub mod error; pub use error::{ApiError, Error, Result};
+pub mod builder; pub use builder::ApiClientBuilder;
+pub mod transport; use transport::{Body, Request, Response, Transport};
+pub mod retry; pub use retry::RetryPolicy;
+pub mod ratelimit; use ratelimit::{RateLimiter, RequestKind};
+pub mod cooldown; use cooldown::{CooldownTracker, Reservation};
+pub mod post; pub use post::{InvalidPost, Post, PostOutcome, PostRejection, PostTarget};
+pub mod attachment; pub use attachment::Attachment;
+pub mod imaging; use imaging::{ImageError, ImageFormat, ImagePipeline};
+pub mod imagecache; use imagecache::{ImageCache, PartialFile};
+pub mod paging; use paging::{Page, PageStreamOptions};
+pub mod refresh; use refresh::{ThreadChange, ThreadUpdate};
+pub mod locate; use locate::{LocationCache, PostLocation};
+pub mod harvest; use harvest::{HarvestFailure, HarvestManifest, HarvestOptions, HarvestProgress, ManifestEntry};
 
 
 
+// get_full_thread 默认同时请求的页数
+const FULL_THREAD_CONCURRENCY: usize = 4;
+
+
 #[derive(Clone, Debug)]
 pub struct ApiClient {
-    pub auth_cookie: Option<UserCookie>,
+    cookie_jar: CookieJar,
     pub feed_uuid: Option<String>,
-    client: reqwest::Client,
+    transport: Arc<dyn Transport>,
     base_url: String,
-    cdn_path_list: Option<cdnpath::CdnPathList>,
+    post_base_url: String,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    cooldown: Option<Arc<CooldownTracker>>,
+    image_pipeline: Option<ImagePipeline>,
+    cdn: Arc<CdnSelector>,
+    image_cache: Arc<ImageCache>,
+    keep_tips: bool,
+    locations: LocationCache,
 }
 
-const BASE_URL: &str = "https://api.nmb.best";
-
-
 impl ApiClient {
 
     // 初始化对象
     pub fn new(auth_cookie: Option<UserCookie>, feed_uuid: Option<String>) -> Self {
         ApiClient {
-            auth_cookie,
+            cookie_jar: CookieJar::new(auth_cookie),
             feed_uuid,
-            client: reqwest::Client::new(),
-            base_url: BASE_URL.to_string(),
-            cdn_path_list: None,
+            transport: Arc::new(transport::ReqwestTransport::default()),
+            base_url: builder::DEFAULT_BASE_URL.to_string(),
+            post_base_url: builder::DEFAULT_POST_BASE_URL.to_string(),
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: Some(Arc::new(RateLimiter::default())),
+            cooldown: Some(Arc::new(CooldownTracker::default())),
+            image_pipeline: Some(ImagePipeline::default()),
+            cdn: Arc::new(CdnSelector::default()),
+            image_cache: Arc::new(ImageCache::default()),
+            keep_tips: false,
+            locations: LocationCache::default(),
         }
     }
 
-    pub async fn init(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn builder() -> ApiClientBuilder {
+        ApiClientBuilder::new()
+    }
+
+    // 当前使用中的饼干
+    pub fn auth_cookie(&self) -> Option<UserCookie> {
+        self.cookie_jar.get()
+    }
+
+    // 切换饼干，对所有共享同一 CookieJar 的客户端克隆立即生效，返回之前的饼干
+    pub fn set_auth_cookie(&self, cookie: Option<UserCookie>) -> Option<UserCookie> {
+        self.cookie_jar.set(cookie)
+    }
+
+    pub fn cookie_jar(&self) -> &CookieJar {
+        &self.cookie_jar
+    }
+
+    // 为请求附加饼干，所有读取、发串、订阅和图片下载请求都经过这里
+    fn authorize(&self, request: Request) -> Request {
+        match self.cookie_jar.header_value() {
+            Some(cookie) => request.header("cookie", cookie),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: Request) -> Result<Response> {
+        self.transport.send(self.authorize(request)).await
+    }
+
+    // 按限流和重试策略发送请求，非 2xx 状态码视为失败
+    async fn execute(&self, kind: RequestKind, request: Request) -> Result<Response> {
+        let mut attempt = 1;
+        let mut request = request;
+        loop {
+            if let Some(limiter) = self.rate_limiter.as_ref() {
+                limiter.acquire(kind).await;
+            }
+            let method = request.method;
+            // 只有可能重试时才复制请求，含流式附图的请求无法复制，也就不会重试
+            let retry = match self.retry_policy.allows(method) {
+                true => request.try_clone(),
+                false => None,
+            };
+            let result = self.send(request).await.and_then(|response| {
+                match response.is_success() {
+                    true => Ok(response),
+                    false => Err(Error::Status {
+                        status: response.status,
+                        url: response.url.clone(),
+                        body: response.text(),
+                    }),
+                }
+            });
+            match (result, retry) {
+                (Err(e), Some(retry)) if self.retry_policy.should_retry(method, &e, attempt) => {
+                    tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
+                    request = retry;
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    // 所有克隆共享的限流器，未启用限流时为 None
+    pub fn rate_limiter(&self) -> Option<&Arc<RateLimiter>> {
+        self.rate_limiter.as_ref()
+    }
+
+    // 所有克隆共享的发串冷却记录，未启用时为 None
+    pub fn cooldown(&self) -> Option<&Arc<CooldownTracker>> {
+        self.cooldown.as_ref()
+    }
+
+    // 发串前按版块冷却时间等待或返回 Error::Cooldown。
+    // 回复时只知道串号，需先查出所属版块
+    async fn wait_cooldown(&self, fid: Option<i64>, tid: Option<i64>) -> Result<Option<Reservation>> {
+        let Some(tracker) = self.cooldown.as_ref() else {
+            return Ok(None);
+        };
+        if !tracker.has_intervals() {
+            let forum_list = self.get_forum_list().await?;
+            tracker.update_intervals(&forum_list);
+        }
+        let fid = match (fid, tid) {
+            (Some(fid), _) => fid,
+            (None, Some(tid)) => match tracker.thread_forum(tid) {
+                Some(fid) => fid,
+                None => {
+                    let Some(fid) = self.thread_forum_id(tid).await? else {
+                        return Ok(None);
+                    };
+                    tracker.set_thread_forum(tid, fid);
+                    fid
+                }
+            },
+            (None, None) => return Ok(None),
+        };
+        let cookie = self.cookie_jar.get().map(|c| c.value).unwrap_or_default();
+        match tracker.reserve(fid, &cookie) {
+            Ok(reservation) => {
+                if !reservation.wait().is_zero() {
+                    tokio::time::sleep(reservation.wait()).await;
+                }
+                Ok(Some(reservation))
+            }
+            Err(retry_after) => Err(Error::Cooldown { fid, retry_after }),
+        }
+    }
+
+    // 串所属的版块ID。api/ref 不一定返回 fid，此时从串的第一页读取
+    async fn thread_forum_id(&self, tid: i64) -> Result<Option<i64>> {
+        if let Some(fid) = self.get_reply(tid).await?.fid.filter(|fid| **fid > 0) {
+            return Ok(Some(*fid));
+        }
+        let fid = self.get_thread_page(tid, 1, false).await?.fid;
+        Ok(fid.map(|fid| *fid).filter(|&fid| fid > 0))
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub async fn init(&self) -> Result<()> {
+        self.refresh_cdn().await
+    }
+
+    // 重新获取图片CDN列表
+    pub async fn refresh_cdn(&self) -> Result<()> {
         let cdn_path_list = self.get_cdn_path().await?;
-        self.cdn_path_list = Some(cdn_path_list);
+        self.cdn.update(cdn_path_list);
         Ok(())
     }
 
-    async fn api_get(&self, api_path: &str, params: Option<HashMap<&str, &str>>) -> Result<json::Value, Box<dyn Error>> {
-        let url = format!("{}/{}", self.base_url, api_path);
-        let mut request = self.client.get(url);
-        let cookie;
-        if let Some(c) = self.auth_cookie.as_ref() {
-            cookie = &c.value;
-            request = request.header(reqwest::header::COOKIE, cookie);
+    // CDN列表超过刷新间隔时重新获取，获取失败但已有旧列表时继续使用旧列表
+    async fn ensure_cdn(&self) -> Result<()> {
+        if self.cdn.needs_refresh()
+            && let Err(e) = self.refresh_cdn().await
+            && self.cdn.list().is_none()
+        {
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // 获取到的图片CDN列表，尚未获取时为 None
+    pub fn cdn_path_list(&self) -> Option<CdnPathList> {
+        self.cdn.list()
+    }
+
+    // 所有克隆共享的CDN选择器
+    pub fn cdn_selector(&self) -> &Arc<CdnSelector> {
+        &self.cdn
+    }
+
+    // 当前选中的CDN，未调用 init() 时为默认CDN
+    fn current_cdn(&self) -> CdnPath {
+        self.cdn.select()
+            .unwrap_or_else(|| CdnPath { rate: 1.0, url: cdnpath::DEFAULT_CDN_URL.to_string() })
+    }
+
+    // 串或回复附图的地址，没有附图时为 None
+    pub fn image_url(&self, post: &forum::Thread) -> Option<String> {
+        self.current_cdn().url_for(ImageVariant::Image, &post.img, &post.ext)
+    }
+
+    // 串或回复附图缩略图的地址，没有附图时为 None
+    pub fn thumb_url(&self, post: &forum::Thread) -> Option<String> {
+        self.current_cdn().url_for(ImageVariant::Thumb, &post.img, &post.ext)
+    }
+
+    // 测量每个CDN的响应延迟，连接失败或返回 5xx 的CDN会被标记为不可用
+    pub async fn probe_cdn_latency(&self) -> Result<Vec<(CdnPath, Option<Duration>)>> {
+        self.ensure_cdn().await?;
+        let mut results = Vec::new();
+        for cdn in self.cdn.list().unwrap_or_default() {
+            if let Some(limiter) = self.rate_limiter.as_ref() {
+                limiter.acquire(RequestKind::Image).await;
+            }
+            let start = Instant::now();
+            let latency = match self.send(Request::get(cdn.url.clone())).await {
+                Ok(response) if response.status < 500 => {
+                    let latency = start.elapsed();
+                    self.cdn.record_latency(&cdn.url, latency);
+                    Some(latency)
+                }
+                _ => {
+                    self.cdn.mark_unhealthy(&cdn.url);
+                    None
+                }
+            };
+            results.push((cdn, latency));
         }
+        Ok(results)
+    }
+
+    // 从CDN获取图片，当前CDN失败时自动换用下一个
+    pub async fn fetch_from_cdn(&self, variant: ImageVariant, img: &str, ext: &str) -> Result<Response> {
+        self.cdn_request(variant, img, ext, None).await
+    }
+
+    // 依次从各CDN请求图片，连接失败或 5xx 的CDN会被标记为不可用并换下一个，
+    // 其它状态码原样返回，由调用者处理。
+    // 传入文件时以流的形式追加写入，并从文件现有长度处续传
+    pub(crate) async fn cdn_request(
+        &self,
+        variant: ImageVariant,
+        img: &str,
+        ext: &str,
+        mut file: Option<&mut PartialFile>,
+    ) -> Result<Response> {
+        if img.is_empty() {
+            return Err(Error::NoImage);
+        }
+        self.ensure_cdn().await.ok();
+        let mut candidates = self.cdn.candidates();
+        if candidates.is_empty() {
+            candidates.push(self.current_cdn());
+        }
+        let mut last_error = None;
+        for cdn in candidates {
+            let Some(url) = cdn.url_for(variant, img, ext) else {
+                return Err(Error::NoImage);
+            };
+            if let Some(limiter) = self.rate_limiter.as_ref() {
+                limiter.acquire(RequestKind::Image).await;
+            }
+            let request = self.authorize(Request::get(url));
+            let result = match file.as_deref_mut() {
+                Some(file) => {
+                    let offset = file.offset().await?;
+                    let request = match offset {
+                        0 => request,
+                        _ => request.header("range", format!("bytes={offset}-")),
+                    };
+                    self.transport.send_streaming(request, file).await
+                }
+                None => self.transport.send(request).await,
+            };
+            match result {
+                Ok(response) if response.status < 500 => {
+                    self.cdn.mark_healthy(&cdn.url);
+                    return Ok(response);
+                }
+                Ok(response) => {
+                    self.cdn.mark_unhealthy(&cdn.url);
+                    last_error = Some(Error::Status {
+                        status: response.status,
+                        url: response.url.clone(),
+                        body: response.text(),
+                    });
+                }
+                Err(e) => {
+                    self.cdn.mark_unhealthy(&cdn.url);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(Error::NoImage))
+    }
+
+    pub fn image_cache(&self) -> &Arc<ImageCache> {
+        &self.image_cache
+    }
+
+    // 下载串或回复的附图（原图或缩略图）到本地缓存，返回缓存中的文件路径。
+    // 已缓存的图片不会再发出请求；未下载完的部分下次会从断点继续
+    pub async fn download_image(&self, post: &forum::Thread, variant: ImageVariant) -> Result<PathBuf> {
+        self.download_image_file(variant, &post.img, &post.ext).await
+    }
+
+    // 按 img、ext 字段下载附图到本地缓存
+    pub async fn download_image_file(&self, variant: ImageVariant, img: &str, ext: &str) -> Result<PathBuf> {
+        if img.is_empty() {
+            return Err(Error::NoImage);
+        }
+        if let Some(path) = self.image_cache.lookup(variant, img, ext).await? {
+            return Ok(path);
+        }
+        let _guard = self.image_cache.lock(variant, img, ext).await;
+        if let Some(path) = self.image_cache.lookup(variant, img, ext).await? {
+            return Ok(path);
+        }
+        let partial = self.image_cache.partial_path(variant, img, ext)
+            .ok_or_else(|| ImageError::Unsupported(format!("invalid image path {img}{ext}")))?;
+        let mut file = PartialFile::open(&partial).await?;
+        let mut restarted = false;
+        loop {
+            let response = self.cdn_request(variant, img, ext, Some(&mut file)).await?;
+            // 此前尝试的CDN可能已写入部分内容，以当前长度为准
+            let length = file.offset().await?;
+            match response.status {
+                // 200 时已清空之前的部分写入完整内容，206 为续传的部分
+                200 | 206 => break,
+                // 已下载的部分就是完整的文件
+                416 if imagecache::content_range(response.header("content-range").unwrap_or_default())
+                    .is_some_and(|(_, total)| total == Some(length)) => break,
+                // 长度对不上，清空后重新下载一次
+                416 if !restarted => {
+                    restarted = true;
+                    file.truncate().await?;
+                }
+                status => {
+                    drop(file);
+                    tokio::fs::remove_file(&partial).await.ok();
+                    return Err(Error::Status { status, url: response.url.clone(), body: response.text() });
+                }
+            }
+        }
+        drop(file);
+
+        let mut head = [0u8; 16];
+        let len = {
+            use tokio::io::AsyncReadExt;
+            let mut file = tokio::fs::File::open(&partial).await?;
+            file.read(&mut head).await?
+        };
+        if let Some(expected) = ImageFormat::from_ext(ext) {
+            let actual = ImageFormat::sniff(&head[..len]);
+            if actual != Some(expected) {
+                tokio::fs::remove_file(&partial).await.ok();
+                return Err(ImageError::Mismatch {
+                    expected: expected.mime().to_string(),
+                    actual: actual.map(|f| f.mime()).unwrap_or("unknown").to_string(),
+                }.into());
+            }
+        }
+        self.image_cache.commit(variant, img, ext, &partial).await
+    }
+
+    // 下载串内所有页的附图（含主串），同时进行的下载数量受 options 限制，
+    // 每张附图结束时回调 progress。单张失败不会中断，记录在清单的 failures 中
+    pub async fn harvest_thread_images(
+        &self,
+        tid: i64,
+        options: &HarvestOptions,
+        mut progress: impl FnMut(HarvestProgress<'_>) + Send,
+    ) -> Result<HarvestManifest> {
+        use futures::StreamExt;
+
+        let posts = self.thread_image_posts(tid, options.is_po_only()).await?;
+        let variant = options.image_variant();
+        let total = posts.len();
+        let mut downloads = futures::stream::iter(posts)
+            .map(|post| async move {
+                let result = self.download_image_file(variant, &post.img, &post.ext).await;
+                (post, result)
+            })
+            .buffer_unordered(options.concurrency_limit());
+
+        let mut manifest = HarvestManifest::new(tid, variant);
+        let mut completed = 0;
+        while let Some((post, result)) = downloads.next().await {
+            completed += 1;
+            progress(HarvestProgress { completed, total, post_id: *post.tid, result: &result });
+            match result {
+                Ok(path) => manifest.entries.push(ManifestEntry {
+                    post_id: *post.tid, img: post.img, ext: post.ext, path,
+                }),
+                Err(e) => manifest.failures.push(HarvestFailure {
+                    post_id: *post.tid, img: post.img, ext: post.ext, error: e.to_string(),
+                }),
+            }
+        }
+        manifest.entries.sort_by_key(|e| e.post_id);
+        manifest.failures.sort_by_key(|e| e.post_id);
+
+        if let Some(path) = options.manifest_path() {
+            manifest.write(path).await?;
+        }
+        Ok(manifest)
+    }
+
+    // 逐页读取串，收集带附图的主串和回复
+    async fn thread_image_posts(&self, tid: i64, po_only: bool) -> Result<Vec<ThreadReply>> {
+        let mut posts: Vec<ThreadReply> = Vec::new();
+        let mut page = 1;
+        loop {
+            let mut thread = match self.get_thread_paged(tid, page, po_only).await {
+                Ok(thread) => thread,
+                Err(Error::Api(ApiError::PageOutOfRange(_))) if page > 1 => break,
+                Err(e) => return Err(e),
+            };
+            if page == 1 && thread.has_image() {
+                posts.push(thread.data.clone());
+            }
+            for reply in thread.data.replies.take().unwrap_or_default() {
+                if reply.has_image() && !reply.is_tips() && !posts.iter().any(|p| *p.tid == *reply.tid) {
+                    posts.push(reply);
+                }
+            }
+            if thread.is_last() {
+                break;
+            }
+            page += 1;
+        }
+        Ok(posts)
+    }
+
+    async fn api_get<T>(&self, api_path: &str, params: Option<HashMap<&str, &str>>) -> Result<T>
+        where T: DeserializeOwned
+    {
+        let url = format!("{}/{}", self.base_url, api_path);
+        let mut request = Request::get(url);
         if let Some(params) = params {
-            request = request.query(&params);
+            let mut params: Vec<_> = params.into_iter().collect();
+            params.sort();
+            for (key, value) in params {
+                request = request.query(key, value);
+            }
+        }
+        let response = self.execute(RequestKind::Read, request).await?;
+        let (json, body) = Self::read_json(response)?;
+        if let Some(e) = ApiError::from_payload(&json) {
+            return Err(e.into());
+        }
+        Self::decode_json(json, body)
+    }
+
+    // 检查状态码并读取JSON响应体，同时保留原始响应体以便出错时排查
+    fn read_json(response: Response) -> Result<(json::Value, String)> {
+        let body = response.text();
+        if !response.is_success() {
+            return Err(Error::Status { status: response.status, url: response.url, body });
+        }
+        match json::from_str::<json::Value>(&body) {
+            Ok(json) => Ok((json, body)),
+            Err(source) => Err(Error::Decode { path: ".".to_string(), source, body }),
         }
-        let response = request.send().await?;
-        let json: json::Value = response.json().await?;
-        Ok(json)
+    }
+
+    // 将JSON解析为指定类型，解析失败时记录出错字段的路径
+    fn decode_json<T>(json: json::Value, body: String) -> Result<T>
+        where T: DeserializeOwned
+    {
+        serde_path_to_error::deserialize(json).map_err(|e| Error::Decode {
+            path: e.path().to_string(),
+            source: e.into_inner(),
+            body,
+        })
     }
 
     // 获取图片CDN地址
@@ -64,27 +520,21 @@ impl ApiClient {
     // 则图片地址为 https://image.nmb.best/image/2022-06-18/62acedc59ef24.png，
     // 缩略图地址为 https://image.nmb.best/thumb/2022-06-18/62acedc59ef24.png。
     // https://github.com/TransparentLC/xdcmd/wiki/%E8%87%AA%E5%B7%B1%E6%95%B4%E7%90%86%E7%9A%84-X-%E5%B2%9B%E5%8C%BF%E5%90%8D%E7%89%88-API-%E6%96%87%E6%A1%A3#%E5%85%B6%E4%BB%96%E7%9A%84%E8%AF%B4%E6%98%8E-1
-    async fn get_cdn_path(&self) -> Result<CdnPathList, Box<dyn Error>> {
+    async fn get_cdn_path(&self) -> Result<CdnPathList> {
         let api_path = "api/getCDNPath";
-        let json = self.api_get(api_path, None).await?;
-        let cdn_path_list = serde_json::from_value::<CdnPathList>(json)?;
-        Ok(cdn_path_list)
+        self.api_get(api_path, None).await
     }
 
     // 获取板块列表
-    pub async fn get_forum_list(&self) -> Result<ForumList, Box<dyn Error>> {
+    pub async fn get_forum_list(&self) -> Result<ForumList> {
         let api_path = "api/getForumList";
-        let json = self.api_get(api_path, None).await?;
-        let forum_list = serde_json::from_value::<ForumList>(json)?;
-        Ok(forum_list)
+        self.api_get(api_path, None).await
     }
 
     // 获取时间线列表
-    pub async fn get_timeline_list(&self) -> Result<TimelineList, Box<dyn Error>> {
+    pub async fn get_timeline_list(&self) -> Result<TimelineList> {
         let api_path = "api/getTimelineList";
-        let json = self.api_get(api_path, None).await?;
-        let timeline_list = serde_json::from_value::<TimelineList>(json)?;
-        Ok(timeline_list)
+        self.api_get(api_path, None).await
     }
 
     // 查看版面，fid为版面ID，page为页数（可置空）
@@ -93,7 +543,7 @@ impl ApiClient {
         api_path: &str,
         id: Option<&str>,
         page: Option<&str>,
-    ) -> Result<ThreadList, Box<dyn Error>> {
+    ) -> Result<ThreadList> {
         let mut params = HashMap::new();
         if let Some(id) = id{
             params.insert("id", id);
@@ -105,9 +555,7 @@ impl ApiClient {
             false => Some(params),
             true => None,
         };
-        let json = self.api_get(api_path, params).await?;
-        let thread_list = serde_json::from_value::<ThreadList>(json)?;
-        Ok(thread_list)
+        self.api_get(api_path, params).await
     }
 
     #[inline]
@@ -115,7 +563,7 @@ impl ApiClient {
         &self,
         fid: FID,
         page: NUM,
-    ) -> Result<ThreadList, Box<dyn Error>>
+    ) -> Result<ThreadList>
         where
             FID: Display,
             NUM: Display,
@@ -132,7 +580,7 @@ impl ApiClient {
         &self,
         tlid: TLID,
         page: NUM,
-    ) -> Result<ThreadList, Box<dyn Error>>
+    ) -> Result<ThreadList>
         where
             TLID: Display,
             NUM: Display,
@@ -150,7 +598,7 @@ impl ApiClient {
         tid: TID,
         page: NUM,
         po_only: bool,
-    ) -> Result<forum::Thread, Box<dyn Error>>
+    ) -> Result<forum::Thread>
         where
             TID: Display,
             NUM: Display,
@@ -164,23 +612,302 @@ impl ApiClient {
         params.insert("id", rid.as_str());
         let page = page.to_string();
         params.insert("page", page.as_str());
-        let json = self.api_get(api_path, Some(params)).await?;
-        let thread = serde_json::from_value::<forum::Thread>(json)?;
+        let mut thread: forum::Thread = self.api_get(api_path, Some(params)).await?;
+        if !self.keep_tips {
+            thread.strip_tips();
+        }
         Ok(thread)
     }
 
-    pub async fn get_reply<TID>(&self, tid: TID) -> Result<ThreadReply, Box<dyn Error>>
+    // 读取串的一页，附带页数、是否有下一页等分页信息
+    pub async fn get_thread_paged(&self, tid: i64, page: usize, po_only: bool) -> Result<Page<forum::Thread>> {
+        let thread = self.get_thread_page(tid, page.max(1), po_only).await?;
+        Ok(Page::from_thread(page, thread))
+    }
+
+    // 读取整个串的所有回复，合并为一个 Thread 返回
+    pub async fn get_full_thread(&self, tid: i64) -> Result<forum::Thread> {
+        self.get_full_thread_with(tid, FULL_THREAD_CONCURRENCY).await
+    }
+
+    // 同 get_full_thread，最多同时请求 concurrency 页。
+    // 读取期间有新回复导致翻页时，跨页重复的回复只保留一条，最终按串号排序
+    pub async fn get_full_thread_with(&self, tid: i64, concurrency: usize) -> Result<forum::Thread> {
+        use futures::StreamExt;
+
+        let first = self.get_thread_paged(tid, 1, false).await?;
+        let last = first.total_pages;
+        let mut has_next = first.has_next;
+        let mut thread = first.into_inner();
+        let mut replies = thread.replies.take().unwrap_or_default();
+
+        // 读取期间有回复被删除导致页数变少时，超出范围的页视为已到末尾
+        let pages: Vec<Option<Page<forum::Thread>>> = futures::stream::iter(2..=last)
+            .map(|page| async move {
+                match self.get_thread_paged(tid, page, false).await {
+                    Ok(page) => Ok(Some(page)),
+                    Err(Error::Api(ApiError::PageOutOfRange(_))) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_>>()?;
+        for page in pages {
+            let Some(page) = page else {
+                has_next = false;
+                break;
+            };
+            has_next = page.has_next;
+            thread.reply_count = page.reply_count.or(thread.reply_count);
+            replies.extend(page.into_inner().replies.unwrap_or_default());
+        }
+        self.read_tail_pages(tid, last, has_next, &mut replies).await?;
+        refresh::dedup_replies(&mut replies);
+        thread.replies = Some(replies);
+        Ok(thread)
+    }
+
+    // 最后一页在读取期间被填满时，从 last 之后继续往后读，直到没有下一页
+    async fn read_tail_pages(&self, tid: i64, mut last: usize, mut has_next: bool, replies: &mut Vec<forum::ThreadReply>) -> Result<()> {
+        while has_next {
+            last += 1;
+            let page = match self.get_thread_paged(tid, last, false).await {
+                Ok(page) => page,
+                Err(Error::Api(ApiError::PageOutOfRange(_))) => break,
+                Err(e) => return Err(e),
+            };
+            has_next = page.has_next;
+            if page.real_reply_count() == 0 {
+                break;
+            }
+            replies.extend(page.into_inner().replies.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    // 根据之前读取的串，只读取末尾需要的页，返回此后的新回复以及 Sage、隐藏、删除等变化
+    pub async fn refresh_thread(&self, previous: &forum::Thread) -> Result<ThreadUpdate> {
+        let tid = *previous.tid;
+        let last_id = refresh::last_known_id(previous);
+        let first = match self.get_thread_paged(tid, 1, false).await {
+            Ok(first) => first,
+            Err(Error::Api(ApiError::ThreadNotFound(_))) => return Ok(ThreadUpdate {
+                head: None,
+                new_replies: Vec::new(),
+                changes: vec![ThreadChange::Deleted],
+            }),
+            Err(e) => return Err(e),
+        };
+        let total = first.total_pages;
+        let mut has_next = first.has_next;
+        let mut head = first.into_inner();
+        let mut replies = head.replies.take().unwrap_or_default();
+        let changes = refresh::diff_head(previous, &head);
+
+        // 从最后一页往前读，直到遇到已知的回复
+        let mut page = total;
+        let mut reached = false;
+        while page > 1 && !reached {
+            let current = self.get_thread_paged(tid, page, false).await?;
+            if page == total {
+                has_next = current.has_next;
+            }
+            let current = current.into_inner().replies.unwrap_or_default();
+            reached = current.iter().any(|reply| !reply.is_tips() && *reply.tid <= last_id);
+            replies.extend(current);
+            page -= 1;
+        }
+        self.read_tail_pages(tid, total, has_next, &mut replies).await?;
+        replies.retain(|reply| !reply.is_tips() && *reply.tid > last_id);
+        refresh::dedup_replies(&mut replies);
+        Ok(ThreadUpdate {
+            head: Some(head),
+            new_replies: replies,
+            changes,
+        })
+    }
+
+    // 找出串中包含某条回复的页，返回该页内容；回复不在该串中（或已被删除）时为 None。
+    // 串号随时间递增，按页二分查找，请求次数约为 log2(页数)
+    pub async fn find_reply_page(&self, tid: i64, rid: i64) -> Result<Option<Page<forum::Thread>>> {
+        let first = self.get_thread_paged(tid, 1, false).await?;
+        if rid == tid {
+            return Ok(Some(first));
+        }
+        if rid < tid {
+            return Ok(None);
+        }
+
+        let (mut low, mut high) = (1, first.total_pages);
+        let mut current = first;
+        loop {
+            let ids: Vec<i64> = current.replies.iter()
+                .flatten()
+                .filter(|reply| !reply.is_tips())
+                .map(|reply| *reply.tid)
+                .collect();
+            match (ids.iter().min(), ids.iter().max()) {
+                (Some(&min), _) if rid < min => high = current.page - 1,
+                (_, Some(&max)) if rid > max => low = current.page + 1,
+                (Some(_), Some(_)) => return Ok(ids.contains(&rid).then_some(current)),
+                // 空页说明串在读取期间变短了，往前找
+                _ => high = current.page - 1,
+            }
+            if low > high {
+                return Ok(None);
+            }
+            let middle = low + (high - low) / 2;
+            current = self.get_thread_paged(tid, middle, false).await?;
+        }
+    }
+
+    pub async fn get_reply<TID>(&self, tid: TID) -> Result<ThreadReply>
         where TID: Display
     {
         let api_path = "api/ref";
         let rid = tid.to_string();
         let params: [(&'static str, &str); 1] = [("id", rid.as_str())];
-        let json = self.api_get(api_path, Some(params.into())).await?;
-        let reply = serde_json::from_value::<ThreadReply>(json)?;
-        Ok(reply)
+        self.api_get(api_path, Some(params.into())).await
+    }
+
+    // 逐条读取版块中的串，自动翻页直到最后一页
+    pub fn forum_threads_stream(&self, fid: i64, options: &PageStreamOptions) -> impl Stream<Item = Result<forum::Thread>> + Send + '_ {
+        paging::paged(options, move |page| async move {
+            Ok((self.get_threads_from_forum(fid, page).await?, false))
+        })
+    }
+
+    // 逐条读取时间线中的串，自动翻页直到最后一页
+    pub fn timeline_threads_stream(&self, tlid: i64, options: &PageStreamOptions) -> impl Stream<Item = Result<forum::Thread>> + Send + '_ {
+        paging::paged(options, move |page| async move {
+            Ok((self.get_threads_from_timeline(tlid, page).await?, false))
+        })
+    }
+
+    // 逐条读取订阅中的串，自动翻页直到最后一页
+    pub fn feed_threads_stream(&self, options: &PageStreamOptions) -> impl Stream<Item = Result<forum::Thread>> + Send + '_ {
+        paging::paged(options, move |page| async move {
+            Ok((self.get_threads_from_feed(page).await?, false))
+        })
+    }
+
+    // 逐条读取串的回复（不含主串本身），自动翻页直到最后一页
+    pub fn thread_replies_stream(&self, tid: i64, po_only: bool, options: &PageStreamOptions) -> impl Stream<Item = Result<ThreadReply>> + Send + '_ {
+        paging::paged(options, move |page| async move {
+            let page = self.get_thread_paged(tid, page, po_only).await?;
+            let last = page.is_last();
+            Ok((page.into_inner().replies.unwrap_or_default(), last))
+        })
+    }
+
+    // 根据串号找出其所属的主串、版块和所在页，找到所在页的结果会被缓存。
+    // api/ref 返回了 resto 时直接使用，否则从网页版的引用页中解析，仍无法确定时返回 Error::UnknownParent
+    pub async fn locate_post(&self, id: i64) -> Result<PostLocation> {
+        if let Some(location) = self.locations.get(id) {
+            return Ok(location);
+        }
+        let post = self.get_reply(id).await?;
+        let tid = match post.resto.map(|resto| resto.into_inner()) {
+            Some(0) => id,
+            Some(resto) => resto,
+            None => self.get_ref_thread_id(id).await?.ok_or(Error::UnknownParent(id))?,
+        };
+        let page = self.find_reply_page(tid, id).await?;
+        let fid = post.fid.map(|fid| fid.into_inner())
+            .or_else(|| page.as_ref().and_then(|page| page.fid.map(|fid| fid.into_inner())))
+            .filter(|&fid| fid > 0);
+        let location = PostLocation {
+            post_id: id,
+            tid,
+            fid,
+            page: page.map(|page| page.page),
+        };
+        if let (Some(cooldown), Some(fid)) = (&self.cooldown, fid) {
+            cooldown.set_thread_forum(tid, fid);
+        }
+        // 找不到所在页可能只是暂时的（如串正在翻页），不缓存
+        if location.page.is_some() {
+            self.locations.insert(location);
+        }
+        Ok(location)
+    }
+
+    pub fn location_cache(&self) -> &LocationCache {
+        &self.locations
+    }
+
+    // 网页版引用页中的主串串号
+    async fn get_ref_thread_id(&self, id: i64) -> Result<Option<i64>> {
+        let url = format!("{}/Home/Forum/ref", self.post_base_url);
+        let request = Request::get(url).query("id", id.to_string());
+        let response = self.execute(RequestKind::Read, request).await?;
+        Ok(locate::parse_ref_page(&response.text()))
+    }
+
+    // 发串或回复，发送前检查内容、处理附图并等待版块的发串冷却
+    pub async fn send_post(&self, mut post: Post) -> Result<PostOutcome> {
+        post.validate()?;
+        if let Some(pipeline) = self.image_pipeline.as_ref()
+            && let Some(image) = post.take_image_for_processing()
+        {
+            post = post.attachment(pipeline.process_attachment(image).await?);
+        }
+        let reservation = match post.target() {
+            PostTarget::NewThread { fid } => self.wait_cooldown(Some(fid), None).await?,
+            PostTarget::Reply { tid } => self.wait_cooldown(None, Some(tid)).await?,
+        };
+        let target = post.target();
+        let resolve = post.resolves_id()
+            .then(|| post.content_text().map(str::to_string));
+        let action_url = format!("{}/{}", self.post_base_url, post.action_path());
+        let form = post.into_form();
+        let request = Request::post(action_url).body(Body::Multipart(form));
+
+        let result = match self.execute(RequestKind::Post, request).await {
+            Ok(res) => PostOutcome::parse(&res.text()).map_err(Error::from),
+            Err(e) => Err(e),
+        };
+        // 只有站点确认发串成功才计入冷却，失败时归还预定
+        if let Some(reservation) = reservation
+            && result.is_ok()
+        {
+            reservation.commit();
+        }
+        let mut outcome = result?;
+        // 发串已经成功，查询失败时不能返回错误，否则调用方重试会重复发串
+        if let Some(content) = resolve {
+            match self.resolve_last_post(target, content.as_deref()).await {
+                Ok(post) => outcome.post = post,
+                Err(e) => outcome.resolve_error = Some(e.to_string()),
+            }
+        }
+        Ok(outcome)
+    }
+
+    // 当前饼干最近发出的串或回复
+    pub async fn get_last_post(&self) -> Result<forum::Thread> {
+        self.api_get("api/getLastPost", None).await
     }
 
-    // 发新串
+    // 查询刚发出的串，站点可能稍有延迟，不一致时稍等后重查几次
+    async fn resolve_last_post(&self, target: PostTarget, content: Option<&str>) -> Result<Option<forum::Thread>> {
+        for attempt in 0..3 {
+            if attempt > 0 {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+            let last = self.get_last_post().await?;
+            if post::is_same_post(target, content, &last) {
+                return Ok(Some(last));
+            }
+        }
+        Ok(None)
+    }
+
+    // 发新串，新代码请使用 send_post
+    #[allow(clippy::too_many_arguments)]
     pub async fn post_new_thread<FID>(
         &self,
         fid: FID,
@@ -190,57 +917,18 @@ impl ApiClient {
         content: Option<&str>,
         img_filepath: Option<&str>,
         img_watermark: Option<bool>,
-    ) -> Result<String, Box<dyn Error>>
+    ) -> Result<PostOutcome>
         where
             FID: Display,
     {
-        let action_url = "https://www.nmbxd1.com/Home/Forum/doPostThread.html";
-        let mut form = multipart::Form::new()
-            .text("fid", fid.to_string());
-
-        if let Some(t) = title {
-            form = form.text("title", t.to_string());
-        }
-        if let Some(n) = name {
-            form = form.text("name", n.to_string());
-        }
-        if let Some(e) = email {
-            form = form.text("email", e.to_string());
-        }
-        if let Some(c) = content {
-            form = form.text("content", c.to_string());
-        }
-
-        if let Some(img_filepath) = img_filepath {
-            let file_content = tokio::fs::read(img_filepath).await?;
-            let file_name = std::path::Path::new(img_filepath)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("file")
-                .to_string();
-            let part = multipart::Part::bytes(file_content).file_name(file_name);
-            form = form.part("image", part);
-        }
-        if let Some(true) = img_watermark {
-            form = form.text("water", "true");
-        }
-
-        let mut request = self.client.post(action_url).multipart(form);
-        if let Some(cookie) = self.auth_cookie.as_ref() {
-            let cookie_header = format!("userhash={}", cookie.value);
-            request = request.header("cookie", cookie_header);
-        }
-
-        let res = request.send().await?;
-        let text = res.text().await?;
-        if text.contains("<h1>:)</h1>") {
-            Ok(String::default())
-        } else {
-            Err(text.into())
-        }
+        let fid = fid.to_string();
+        let fid = fid.parse().map_err(|_| InvalidPost::InvalidTarget(fid))?;
+        let post = Self::fill_post(Post::new_thread(fid), title, name, email, content, img_filepath, img_watermark);
+        self.send_post(post).await
     }
 
-    // 发评论
+    // 发评论，新代码请使用 send_post
+    #[allow(clippy::too_many_arguments)]
     pub async fn post_thread_reply<TID>(
         &self,
         tid: TID,
@@ -250,68 +938,52 @@ impl ApiClient {
         content: Option<&str>,
         img_filepath: Option<&str>,
         img_watermark: Option<bool>,
-    ) -> Result<String, Box<dyn Error>>
+    ) -> Result<PostOutcome>
         where
             TID: Display,
     {
-        let action_url = "https://www.nmbxd1.com/Home/Forum/doReplyThread.html";
-        let mut form = multipart::Form::new()
-            .text("resto", tid.to_string());
+        let tid = tid.to_string();
+        let tid = tid.parse().map_err(|_| InvalidPost::InvalidTarget(tid))?;
+        let post = Self::fill_post(Post::reply(tid), title, name, email, content, img_filepath, img_watermark);
+        self.send_post(post).await
+    }
 
+    fn fill_post(
+        mut post: Post,
+        title: Option<&str>,
+        name: Option<&str>,
+        email: Option<&str>,
+        content: Option<&str>,
+        img_filepath: Option<&str>,
+        img_watermark: Option<bool>,
+    ) -> Post {
         if let Some(t) = title {
-            form = form.text("title", t.to_string());
+            post = post.title(t);
         }
         if let Some(n) = name {
-            form = form.text("name", n.to_string());
+            post = post.name(n);
         }
         if let Some(e) = email {
-            form = form.text("email", e.to_string());
+            post = post.email(e);
         }
-
         if let Some(c) = content {
-            form = form.text("content", c.to_string());
+            post = post.content(c);
         }
-
-        if let Some(img_filepath) = img_filepath {
-            let file_content = tokio::fs::read(img_filepath).await?;
-            let file_name = std::path::Path::new(img_filepath)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("file")
-                .to_string();
-            let part = multipart::Part::bytes(file_content).file_name(file_name);
-            form = form.part("image", part);
-        }
-        if let Some(true) = img_watermark {
-            form = form.text("water", "true");
-        }
-
-        let mut request = self.client.post(action_url).multipart(form);
-        if let Some(cookie) = self.auth_cookie.as_ref() {
-            let cookie_header = format!("userhash={}", cookie.value);
-            request = request.header("cookie", cookie_header);
-        }
-
-        let res = request.send().await?;
-        let text = res.text().await?;
-        if text.contains("<h1>:)</h1>") {
-            Ok(String::default())
-        } else {
-            Err(text.into())
+        if let Some(path) = img_filepath {
+            post = post.image(path);
         }
+        post.watermark(img_watermark.unwrap_or(false))
     }
 
     // 查看订阅，uuid为订阅id，page为页数（可置空）
-    pub async fn get_threads_from_feed<NUM>(&self, page: NUM) -> Result<ThreadList, Box<dyn Error>>
+    pub async fn get_threads_from_feed<NUM>(&self, page: NUM) -> Result<ThreadList>
         where NUM: Display
     {
         let api_path = "api/feed";
-        let feed_uuid = self.feed_uuid.as_ref().unwrap();
+        let feed_uuid = self.feed_uuid.as_ref().ok_or(Error::MissingFeedUuid)?;
         let page = page.to_string();
         let params: [(&'static str, &str); 2] = [("uuid", feed_uuid.as_str()), ("page", page.as_str())];
-        let json = self.api_get(api_path, Some(params.into())).await?;
-        let thread_list = serde_json::from_value::<ThreadList>(json)?;
-        Ok(thread_list)
+        self.api_get(api_path, Some(params.into())).await
     }
 
     // 添加订阅，uuid为订阅id，rid为串号
@@ -319,12 +991,14 @@ impl ApiClient {
         &self,
         uuid: &str,
         tid: &str,
-    ) -> Result<json::Value, Box<dyn Error>> {
-        let url = format!("{}/api/addFeed?uuid={}", self.base_url, uuid);
-        let params = [("tid", tid)];
-        let res = self.client.post(&url).form(&params).send().await?;
-        let json: json::Value = res.json().await?;
-        Ok(json)
+    ) -> Result<json::Value> {
+        let url = format!("{}/api/addFeed", self.base_url);
+        let request = Request::post(url)
+            .query("uuid", uuid)
+            .body(Body::Form(vec![("tid".to_string(), tid.to_string())]));
+        let res = self.execute(RequestKind::Read, request).await?;
+        let (json, body) = Self::read_json(res)?;
+        Self::decode_json(json, body)
     }
 
     // 删除订阅，uuid为订阅id，rid为串号
@@ -332,12 +1006,14 @@ impl ApiClient {
         &self,
         uuid: &str,
         tid: &str,
-    ) -> Result<json::Value, Box<dyn Error>> {
-        let url = format!("{}/api/delFeed?uuid={}", self.base_url, uuid);
-        let params = [("tid", tid)];
-        let res = self.client.post(&url).form(&params).send().await?;
-        let json: json::Value = res.json().await?;
-        Ok(json)
+    ) -> Result<json::Value> {
+        let url = format!("{}/api/delFeed", self.base_url);
+        let request = Request::post(url)
+            .query("uuid", uuid)
+            .body(Body::Form(vec![("tid".to_string(), tid.to_string())]));
+        let res = self.execute(RequestKind::Read, request).await?;
+        let (json, body) = Self::read_json(res)?;
+        Self::decode_json(json, body)
     }
 
 }
\ No newline at end of file
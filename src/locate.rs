@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+
+/// 一条串或回复在站点中的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PostLocation {
+    /// 查询的串号
+    pub post_id: i64,
+    /// 所属主串的串号，查询的是主串时与 post_id 相同
+    pub tid: i64,
+    /// 所属版块ID
+    pub fid: Option<i64>,
+    /// 在主串中的页码，找不到（如已被删除）时为 None
+    pub page: Option<usize>,
+}
+
+impl PostLocation {
+
+    pub fn is_thread(&self) -> bool {
+        self.post_id == self.tid
+    }
+
+    // 网页版的串链接，如 /t/50000000?page=3
+    pub fn thread_path(&self) -> String {
+        match self.page {
+            Some(page) if page > 1 => format!("/t/{}?page={}", self.tid, page),
+            _ => format!("/t/{}", self.tid),
+        }
+    }
+}
+
+
+/// 串号到位置的缓存，ApiClient 的所有克隆共享同一个实例
+#[derive(Debug, Default, Clone)]
+pub struct LocationCache {
+    entries: Arc<Mutex<HashMap<i64, PostLocation>>>,
+}
+
+impl LocationCache {
+
+    pub fn get(&self, post_id: i64) -> Option<PostLocation> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).get(&post_id).copied()
+    }
+
+    pub fn insert(&self, location: PostLocation) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).insert(location.post_id, location);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+
+// 从网页版的引用页（Home/Forum/ref?id=...）中取出所属主串的串号，
+// 页面中的链接形如 href="/t/50000000?r=50000001"
+pub(crate) fn parse_ref_page(page: &str) -> Option<i64> {
+    let (_, rest) = page.split_once("href=\"/t/")?;
+    let end = rest.find(|c: char| !c.is_ascii_digit())?;
+    rest[..end].parse().ok()
+}
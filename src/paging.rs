@@ -0,0 +1,229 @@
+use std::future::{Future, ready};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{Stream, StreamExt, stream};
+
+use crate::{ApiError, Error, Result};
+use crate::forum::{self, Thread};
+
+
+/// 按页读取列表时的选项
+#[derive(Debug, Clone)]
+pub struct PageStreamOptions {
+    start_page: usize,
+    max_pages: Option<usize>,
+    prefetch: usize,
+}
+
+impl Default for PageStreamOptions {
+    fn default() -> Self {
+        Self {
+            start_page: 1,
+            max_pages: None,
+            prefetch: 0,
+        }
+    }
+}
+
+impl PageStreamOptions {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 从第几页开始读取（从 1 开始），默认第 1 页
+    pub fn start_page(mut self, page: usize) -> Self {
+        self.start_page = page.max(1);
+        self
+    }
+
+    // 最多读取的页数，默认读到最后一页
+    pub fn max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = Some(max_pages);
+        self
+    }
+
+    // 在当前页之外提前请求的页数，默认 0 即逐页请求。
+    // 到达最后一页时，已提前请求的后续页会被丢弃
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+}
+
+
+/// 带分页信息的一页数据
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    /// 当前页码，从 1 开始
+    pub page: usize,
+    /// 总页数，至少为 1
+    pub total_pages: usize,
+    /// 每页的条目数（不含 Tips）
+    pub per_page: usize,
+    /// 是否还有下一页
+    pub has_next: bool,
+    pub data: T,
+}
+
+impl<T> Page<T> {
+
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+
+    pub fn is_last(&self) -> bool {
+        !self.has_next
+    }
+
+    pub fn next_page(&self) -> Option<usize> {
+        self.has_next.then_some(self.page + 1)
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Page<U> {
+        Page {
+            page: self.page,
+            total_pages: self.total_pages,
+            per_page: self.per_page,
+            has_next: self.has_next,
+            data: f(self.data),
+        }
+    }
+}
+
+impl<T> Deref for Page<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl Page<Thread> {
+
+    // 根据串的 reply_count 计算分页信息。站点每页固定 19 条回复，插入的 Tips 不占名额；
+    // 本页不足 19 条时视为最后一页（api/po 的 reply_count 仍是整串的回复数）
+    pub fn from_thread(page: usize, thread: Thread) -> Self {
+        let page = page.max(1);
+        let per_page = forum::REPLIES_PER_PAGE;
+        let full = thread.real_reply_count() >= per_page;
+        let reply_count = thread.reply_count.map(|n| n.into_inner().max(0) as usize);
+        let total_pages = match reply_count {
+            // 本页已满却超出了 reply_count 对应的页数，说明读取期间有新回复
+            Some(n) if full && page * per_page > n => forum::page_count(n).max(page + 1),
+            Some(n) if full => forum::page_count(n).max(page),
+            Some(_) => page,
+            None if full => page + 1,
+            None => page,
+        };
+        Self {
+            page,
+            total_pages,
+            per_page,
+            has_next: page < total_pages,
+            data: thread,
+        }
+    }
+}
+
+
+// 按页号依次调用 fetch，展开为逐条的 Stream。
+// fetch 返回该页的内容以及是否为最后一页；遇到空页或页数超出范围时结束，出错时产出错误后结束
+pub(crate) fn paged<'a, T, F, Fut>(options: &PageStreamOptions, fetch: F) -> impl Stream<Item = Result<T>> + Send + 'a
+where
+    T: Send + 'a,
+    F: FnMut(usize) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<(Vec<T>, bool)>> + Send + 'a,
+{
+    let pages = (options.start_page..).take(options.max_pages.unwrap_or(usize::MAX));
+    // 读到最后一页后不再发起新的请求，已提前发起的请求结果直接丢弃
+    let done = Arc::new(AtomicBool::new(false));
+    let more = {
+        let done = done.clone();
+        move |_: &usize| ready(!done.load(Ordering::Relaxed))
+    };
+    stream::iter(pages)
+        .take_while(more)
+        .map(fetch)
+        .buffered(options.prefetch + 1)
+        .scan(done, |done, page| {
+            if done.load(Ordering::Relaxed) {
+                return ready(None);
+            }
+            ready(match page {
+                Ok((items, _)) if items.is_empty() => None,
+                Ok((items, last)) => {
+                    done.store(last, Ordering::Relaxed);
+                    Some(Ok(items))
+                }
+                Err(Error::Api(ApiError::PageOutOfRange(_))) => None,
+                Err(e) => {
+                    done.store(true, Ordering::Relaxed);
+                    Some(Err(e))
+                }
+            })
+        })
+        .flat_map(|page| match page {
+            Ok(items) => stream::iter(items.into_iter().map(Ok)).left_stream(),
+            Err(e) => stream::once(ready(Err(e))).right_stream(),
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn post(id: i64) -> serde_json::Value {
+        json!({"id": id, "user_hash": "abcdefg", "now": "", "content": "", "img": "", "ext": ""})
+    }
+
+    fn thread(reply_count: Option<i64>, replies: usize, tips: bool) -> Thread {
+        let mut list: Vec<_> = (0..replies as i64).map(|i| post(1000 + i)).collect();
+        if tips {
+            list.insert(0, json!({"id": forum::TIPS_ID, "user_hash": "Tips", "now": "", "content": "", "img": "", "ext": ""}));
+        }
+        let mut thread = post(1);
+        thread["Replies"] = json!(list);
+        if let Some(count) = reply_count {
+            thread["ReplyCount"] = json!(count);
+        }
+        serde_json::from_value(thread).unwrap()
+    }
+
+    #[test]
+    fn page_metadata() {
+        // (说明, 页码, reply_count, 本页回复数, 是否含 Tips, 总页数, 是否有下一页)
+        let cases = [
+            ("没有回复", 1, Some(0), 0, false, 1, false),
+            ("第一页之后还有", 1, Some(45), 19, true, 3, true),
+            ("中间页", 2, Some(45), 19, true, 3, true),
+            ("最后一页不满", 3, Some(45), 7, true, 3, false),
+            ("最后一页恰好满", 2, Some(38), 19, false, 2, false),
+            ("读取期间回复增加", 2, Some(30), 19, false, 3, true),
+            ("Tips 不占名额", 1, Some(18), 18, true, 1, false),
+            ("api/po 的 reply_count 为整串回复数", 1, Some(100), 5, false, 1, false),
+            ("api/po 满页", 1, Some(100), 19, false, 6, true),
+            ("没有 reply_count 且满页", 4, None, 19, false, 5, true),
+            ("没有 reply_count 且不满", 4, None, 3, false, 4, false),
+            ("页码为 0 视为第一页", 0, Some(45), 19, false, 3, true),
+        ];
+        for (name, page, reply_count, replies, tips, total_pages, has_next) in cases {
+            let result = Page::from_thread(page, thread(reply_count, replies, tips));
+            assert_eq!(result.page, page.max(1), "{name}");
+            assert_eq!(result.total_pages, total_pages, "{name}");
+            assert_eq!(result.has_next, has_next, "{name}");
+            assert_eq!(result.per_page, forum::REPLIES_PER_PAGE, "{name}");
+            assert_eq!(result.next_page(), has_next.then_some(page.max(1) + 1), "{name}");
+        }
+    }
+
+    #[test]
+    fn page_count_from_reply_count() {
+        for (reply_count, pages) in [(0, 1), (1, 1), (19, 1), (20, 2), (38, 2), (39, 3)] {
+            assert_eq!(forum::page_count(reply_count), pages, "{reply_count}");
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::attachment::Attachment;
+use crate::forum::Thread;
+use crate::transport::Part;
+
+
+/// 发串的目标：在版块中发新串，或回复某个串
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostTarget {
+    NewThread { fid: i64 },
+    Reply { tid: i64 },
+}
+
+
+/// 发送前检查出的问题
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidPost {
+
+    /// 版块ID或串号不是正整数
+    #[error("invalid post target: {0}")]
+    InvalidTarget(String),
+
+    /// 正文和附图都为空
+    #[error("post has neither content nor image")]
+    Empty,
+
+    /// 标题、名称、邮箱中不能换行
+    #[error("field `{0}` must be a single line")]
+    MultilineField(&'static str),
+}
+
+
+/// 一次发串或回复的全部内容，可分多步构造，发送前会先调用 [`Post::validate`]。
+#[derive(Debug)]
+pub struct Post {
+    target: PostTarget,
+    title: Option<String>,
+    name: Option<String>,
+    email: Option<String>,
+    content: Option<String>,
+    image: Option<Attachment>,
+    watermark: bool,
+    process_image: bool,
+    resolve_id: bool,
+}
+
+impl Post {
+
+    pub fn new(target: PostTarget) -> Self {
+        Self {
+            target,
+            title: None,
+            name: None,
+            email: None,
+            content: None,
+            image: None,
+            watermark: false,
+            process_image: true,
+            resolve_id: false,
+        }
+    }
+
+    // 在版块 fid 中发新串
+    pub fn new_thread(fid: i64) -> Self {
+        Self::new(PostTarget::NewThread { fid })
+    }
+
+    // 回复串 tid
+    pub fn reply(tid: i64) -> Self {
+        Self::new(PostTarget::Reply { tid })
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn content(mut self, content: impl Into<String>) -> Self {
+        self.content = Some(content.into());
+        self
+    }
+
+    // 附图的文件路径，文件名和 MIME 类型由路径推断
+    pub fn image(self, path: impl Into<PathBuf>) -> Self {
+        self.attachment(Attachment::from_path(path))
+    }
+
+    // 来自内存、文件或流的附图
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.image = Some(attachment);
+        self
+    }
+
+    // 是否给附图添加水印
+    pub fn watermark(mut self, watermark: bool) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    // 是否让客户端的 ImagePipeline 处理附图，默认处理。
+    // 关闭后来自流的附图会原样以流的形式上传
+    pub fn process_image(mut self, process: bool) -> Self {
+        self.process_image = process;
+        self
+    }
+
+    // 发送成功后是否通过 api/getLastPost 查询新串/回复，结果见 PostOutcome.post
+    pub fn resolve_id(mut self, resolve: bool) -> Self {
+        self.resolve_id = resolve;
+        self
+    }
+
+    pub fn resolves_id(&self) -> bool {
+        self.resolve_id
+    }
+
+    pub fn target(&self) -> PostTarget {
+        self.target
+    }
+
+    pub fn content_text(&self) -> Option<&str> {
+        self.content.as_deref()
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), InvalidPost> {
+        match self.target {
+            PostTarget::NewThread { fid: id } | PostTarget::Reply { tid: id } if id <= 0 => {
+                return Err(InvalidPost::InvalidTarget(id.to_string()));
+            }
+            _ => {}
+        }
+        let has_content = self.content.as_deref().is_some_and(|c| !c.trim().is_empty());
+        if !has_content && self.image.is_none() {
+            return Err(InvalidPost::Empty);
+        }
+        for (field, value) in [("title", &self.title), ("name", &self.name), ("email", &self.email)] {
+            if value.as_deref().is_some_and(|v| v.contains(['\r', '\n'])) {
+                return Err(InvalidPost::MultilineField(field));
+            }
+        }
+        Ok(())
+    }
+
+    // 取出需要经过 ImagePipeline 处理的附图
+    pub(crate) fn take_image_for_processing(&mut self) -> Option<Attachment> {
+        match self.process_image {
+            true => self.image.take(),
+            false => None,
+        }
+    }
+
+    // 网页版发串表单的提交地址（不含域名）
+    pub(crate) fn action_path(&self) -> &'static str {
+        match self.target {
+            PostTarget::NewThread { .. } => "Home/Forum/doPostThread.html",
+            PostTarget::Reply { .. } => "Home/Forum/doReplyThread.html",
+        }
+    }
+
+    // 转换为 multipart 表单
+    pub(crate) fn into_form(self) -> Vec<Part> {
+        let mut form = vec![match self.target {
+            PostTarget::NewThread { fid } => text_part("fid", fid.to_string()),
+            PostTarget::Reply { tid } => text_part("resto", tid.to_string()),
+        }];
+        for (name, value) in [("title", self.title), ("name", self.name), ("email", self.email), ("content", self.content)] {
+            if let Some(value) = value {
+                form.push(text_part(name, value));
+            }
+        }
+        if let Some(attachment) = self.image {
+            form.push(Part::File { name: "image".to_string(), attachment });
+        }
+        if self.watermark {
+            form.push(text_part("water", "true".to_string()));
+        }
+        form
+    }
+}
+
+
+fn text_part(name: &str, value: String) -> Part {
+    Part::Text { name: name.to_string(), value }
+}
+
+
+/// 发串成功后跳转页面中的信息
+#[derive(Debug, Clone)]
+pub struct PostOutcome {
+    /// 站点给出的提示，如“回复成功”
+    pub message: String,
+    /// 页面自动跳转的目标地址
+    pub redirect: Option<String>,
+    /// 刚发出的串或回复，仅在 Post::resolve_id 开启且查询结果与所发内容一致时存在
+    pub post: Option<Thread>,
+    /// 发串成功但查询刚发出的串失败时的错误信息，此时 post 为 None
+    pub resolve_error: Option<String>,
+}
+
+
+/// 站点拒绝发串时的原因，均带有站点给出的提示
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PostRejection {
+
+    #[error("no cookie: {0}")]
+    NoCookie(String),
+
+    #[error("content too short: {0}")]
+    ContentTooShort(String),
+
+    #[error("posting too fast: {0}")]
+    Cooldown(String),
+
+    #[error("forum closed: {0}")]
+    ForumClosed(String),
+
+    #[error("banned: {0}")]
+    Banned(String),
+
+    #[error("image rejected: {0}")]
+    ImageRejected(String),
+
+    /// 无法归类的提示；页面无法解析时为整个页面的文字
+    #[error("{0}")]
+    Other(String),
+}
+
+impl PostRejection {
+
+    // 根据站点提示的文字判断被拒绝的原因
+    pub fn from_message(message: &str) -> Self {
+        let message = message.to_string();
+        let has = |keys: &[&str]| keys.iter().any(|k| message.contains(k));
+        if has(&["封禁", "封号", "禁止发言", "屏蔽"]) {
+            PostRejection::Banned(message)
+        } else if has(&["频繁", "间隔", "冷却", "稍后"]) {
+            PostRejection::Cooldown(message)
+        } else if has(&["图片", "附件", "文件"]) {
+            PostRejection::ImageRejected(message)
+        } else if has(&["太短", "过短", "不能为空", "没有内容"]) {
+            PostRejection::ContentTooShort(message)
+        } else if has(&["关闭", "锁定", "只读", "不允许"]) {
+            PostRejection::ForumClosed(message)
+        } else if has(&["饼干"]) {
+            PostRejection::NoCookie(message)
+        } else {
+            PostRejection::Other(message)
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            PostRejection::NoCookie(m)
+            | PostRejection::ContentTooShort(m)
+            | PostRejection::Cooldown(m)
+            | PostRejection::ForumClosed(m)
+            | PostRejection::Banned(m)
+            | PostRejection::ImageRejected(m)
+            | PostRejection::Other(m) => m,
+        }
+    }
+}
+
+impl PostOutcome {
+
+    // 解析发串后的跳转页面：
+    // 成功为 <h1>:)</h1><p class="success">…</p>，失败为 <h1>:(</h1><p class="error">…</p>，
+    // 跳转地址在 <a id="href" href="…"> 中
+    pub fn parse(page: &str) -> Result<Self, PostRejection> {
+        if page.contains("<h1>:)</h1>") {
+            let message = extract_between(page, "class=\"success\">", "</p>")
+                .map(clean_text)
+                .unwrap_or_default();
+            let redirect = extract_between(page, "id=\"href\" href=\"", "\"")
+                .filter(|href| !href.starts_with("javascript:"))
+                .map(|href| decode_entities(href.trim()));
+            return Ok(Self { message, redirect, post: None, resolve_error: None });
+        }
+        let message = match extract_between(page, "class=\"error\">", "</p>") {
+            Some(message) => clean_text(message),
+            None => return Err(PostRejection::Other(clean_text(page))),
+        };
+        Err(PostRejection::from_message(&message))
+    }
+}
+
+
+// 判断 api/getLastPost 返回的串是否就是刚发出的内容：
+// 目标版块/串号一致，且正文相同。站点返回的正文是转义后的HTML，需去掉标签并还原实体；
+// 所发的是原始文字（可能含 >>No.、< 等），只去掉空白
+pub(crate) fn is_same_post(target: PostTarget, content: Option<&str>, post: &Thread) -> bool {
+    let resto = post.resto.map(|n| n.into_inner()).unwrap_or(0);
+    let target_ok = match target {
+        PostTarget::NewThread { fid } => resto == 0 && post.fid.is_none_or(|f| f.into_inner() == fid),
+        PostTarget::Reply { tid } => resto == tid,
+    };
+    let strip_whitespace = |s: &str| s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    target_ok && strip_whitespace(content.unwrap_or_default()) == strip_whitespace(&clean_text(&post.content))
+}
+
+
+fn extract_between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let begin = text.find(start)? + start.len();
+    let len = text[begin..].find(end)?;
+    Some(&text[begin..begin + len])
+}
+
+// 去掉HTML标签并合并空白
+fn clean_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    decode_entities(&text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 站点发串后返回的跳转页面
+    fn jump_page(success: bool, message: &str, href: &str) -> String {
+        let (face, class) = match success {
+            true => (":)", "success"),
+            false => (":(", "error"),
+        };
+        format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta http-equiv="Content-Type" content="text/html; charset=utf-8" />
+<title>跳转提示</title>
+</head>
+<body>
+<div class="system-message">
+<h1>{face}</h1>
+<p class="{class}">{message}</p>
+<p class="detail"></p>
+<p class="jump">
+页面自动 <a id="href" href="{href}">跳转</a> 等待时间： <b id="wait">3</b>
+</p>
+</div>
+</body>
+</html>"#)
+    }
+
+    #[test]
+    fn parse_success_page() {
+        let outcome = PostOutcome::parse(&jump_page(true, "回复成功", "/t/50000000")).unwrap();
+        assert_eq!(outcome.message, "回复成功");
+        assert_eq!(outcome.redirect.as_deref(), Some("/t/50000000"));
+        assert!(outcome.post.is_none());
+
+        let outcome = PostOutcome::parse(&jump_page(true, "发帖成功", "/f/综合版1?a=1&amp;b=2")).unwrap();
+        assert_eq!(outcome.redirect.as_deref(), Some("/f/综合版1?a=1&b=2"));
+    }
+
+    #[test]
+    fn parse_rejection_pages() {
+        let back = "javascript:history.back(-1);";
+        let cases = [
+            ("没有饼干不能发串哦", PostRejection::NoCookie as fn(String) -> PostRejection),
+            ("正文内容太短", PostRejection::ContentTooShort),
+            ("标题和正文不能为空", PostRejection::ContentTooShort),
+            ("发串间隔太短，请稍后再试", PostRejection::Cooldown),
+            ("回复过于频繁", PostRejection::Cooldown),
+            ("本版块已关闭", PostRejection::ForumClosed),
+            ("该串已被锁定，不允许回复", PostRejection::ForumClosed),
+            ("该饼干已被封禁", PostRejection::Banned),
+            ("上传的图片格式不支持", PostRejection::ImageRejected),
+            ("附件大小超过限制", PostRejection::ImageRejected),
+            ("未知错误", PostRejection::Other),
+        ];
+        for (message, kind) in cases {
+            let rejection = PostOutcome::parse(&jump_page(false, message, back)).unwrap_err();
+            assert_eq!(rejection, kind(message.to_string()), "{message}");
+            assert_eq!(rejection.message(), message);
+        }
+    }
+
+    fn last_post(resto: i64, content: &str) -> Thread {
+        serde_json::from_value(serde_json::json!({
+            "id": 50000001, "fid": 4, "resto": resto, "user_hash": "abcdefg",
+            "now": "2025-07-31(四)13:49:32", "content": content, "img": "", "ext": "",
+        })).unwrap()
+    }
+
+    #[test]
+    fn same_post_with_quote_and_escaped_text() {
+        let target = PostTarget::Reply { tid: 50000000 };
+        let site = "<font color=\"#789922\">&gt;&gt;No.1</font><br />\nhello";
+        assert!(is_same_post(target, Some(">>No.1\nhello"), &last_post(50000000, site)));
+        assert!(is_same_post(target, Some("a<b & c>d"), &last_post(50000000, "a&lt;b &amp; c&gt;d")));
+        assert!(!is_same_post(target, Some("a<b"), &last_post(50000000, "a")));
+        assert!(!is_same_post(target, Some(">>No.1\nhello"), &last_post(50000002, site)));
+        assert!(!is_same_post(PostTarget::NewThread { fid: 4 }, Some("hello"), &last_post(50000000, "hello")));
+        assert!(is_same_post(PostTarget::NewThread { fid: 4 }, Some("hello"), &last_post(0, "hello")));
+    }
+
+    #[test]
+    fn parse_unknown_page() {
+        let rejection = PostOutcome::parse("<html><body><p>502 Bad Gateway</p></body></html>").unwrap_err();
+        assert_eq!(rejection, PostRejection::Other("502 Bad Gateway".to_string()));
+    }
+
+    #[test]
+    fn validate_posts() {
+        let image = || Attachment::from_bytes(&b"GIF89a"[..], "a.gif", "image/gif");
+        let cases = [
+            ("新串", Post::new_thread(4).content("正文"), Ok(())),
+            ("回复", Post::reply(50000000).content("正文"), Ok(())),
+            ("只有附图", Post::reply(50000000).attachment(image()), Ok(())),
+            ("版块ID为 0", Post::new_thread(0).content("正文"), Err(InvalidPost::InvalidTarget("0".into()))),
+            ("串号为负", Post::reply(-1).content("正文"), Err(InvalidPost::InvalidTarget("-1".into()))),
+            ("没有正文和附图", Post::reply(50000000), Err(InvalidPost::Empty)),
+            ("正文只有空白", Post::reply(50000000).content(" \n\t"), Err(InvalidPost::Empty)),
+            ("正文可以换行", Post::reply(50000000).content("第一行\n第二行"), Ok(())),
+            ("标题换行", Post::reply(50000000).content("正文").title("标题\n"), Err(InvalidPost::MultilineField("title"))),
+            ("名称换行", Post::reply(50000000).content("正文").name("无名\r氏"), Err(InvalidPost::MultilineField("name"))),
+            ("邮箱换行", Post::reply(50000000).content("正文").email("a@b\r\n"), Err(InvalidPost::MultilineField("email"))),
+        ];
+        for (name, post, expected) in cases {
+            assert_eq!(post.validate(), expected, "{name}");
+        }
+    }
+}
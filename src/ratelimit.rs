@@ -0,0 +1,229 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+type WaitObserver = Arc<dyn Fn(RequestKind, Duration) + Send + Sync>;
+
+
+/// 限流时区分的请求种类，各自拥有独立的令牌桶
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    /// 读取接口（查看版面、串、订阅等）
+    Read,
+    /// 发串、回复
+    Post,
+    /// 下载图片
+    Image,
+}
+
+
+/// 令牌桶参数：最多积攒 burst 个令牌，每秒补充 per_second 个。
+/// per_second 必须大于 0，否则用完 burst 后再也无法发出请求，构造限流器时会 panic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        let limit = Self { burst: burst.max(1), per_second };
+        limit.check();
+        limit
+    }
+
+    fn check(&self) {
+        assert!(
+            self.per_second > 0.0 && self.per_second.is_finite(),
+            "RateLimit.per_second must be positive, got {}", self.per_second,
+        );
+    }
+}
+
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+    waited: Duration,
+    acquired: u64,
+}
+
+impl TokenBucket {
+
+    fn new(limit: RateLimit) -> Self {
+        limit.check();
+        Self {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: limit.burst as f64,
+                last: Instant::now(),
+                waited: Duration::ZERO,
+                acquired: 0,
+            }),
+        }
+    }
+
+    // 预定一个令牌，返回需要等待的时间。令牌数可以为负，表示已被排队的调用者预定
+    fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        state.last = now;
+        state.tokens -= 1.0;
+        let wait = match state.tokens < 0.0 {
+            true => Duration::from_secs_f64(-state.tokens / self.limit.per_second),
+            false => Duration::ZERO,
+        };
+        state.waited += wait;
+        state.acquired += 1;
+        wait
+    }
+
+    fn stats(&self) -> (Duration, u64) {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        (state.waited, state.acquired)
+    }
+}
+
+
+/// 客户端内置的限流器，ApiClient 的所有克隆共享同一个实例。
+/// 读取、发串和图片下载分别使用独立的额度。
+pub struct RateLimiter {
+    reads: TokenBucket,
+    posts: TokenBucket,
+    images: TokenBucket,
+    observer: Option<WaitObserver>,
+}
+
+impl Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("reads", &self.reads)
+            .field("posts", &self.posts)
+            .field("images", &self.images)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(
+            RateLimit::new(5, 2.0),
+            RateLimit::new(1, 0.2),
+            RateLimit::new(8, 4.0),
+        )
+    }
+}
+
+impl RateLimiter {
+
+    pub fn new(reads: RateLimit, posts: RateLimit, images: RateLimit) -> Self {
+        Self {
+            reads: TokenBucket::new(reads),
+            posts: TokenBucket::new(posts),
+            images: TokenBucket::new(images),
+            observer: None,
+        }
+    }
+
+    // 每个请求获取令牌后回调请求种类和本次等待的时间（不需要等待时为 0），
+    // 回调在发出请求的任务中执行，可用于记录单个请求被限流的时长
+    pub fn on_wait<F>(mut self, f: F) -> Self
+        where F: Fn(RequestKind, Duration) + Send + Sync + 'static
+    {
+        self.observer = Some(Arc::new(f));
+        self
+    }
+
+    fn bucket(&self, kind: RequestKind) -> &TokenBucket {
+        match kind {
+            RequestKind::Read => &self.reads,
+            RequestKind::Post => &self.posts,
+            RequestKind::Image => &self.images,
+        }
+    }
+
+    pub fn limit(&self, kind: RequestKind) -> RateLimit {
+        self.bucket(kind).limit
+    }
+
+    // 等待直到可以发出一个该种类的请求，返回实际等待的时间
+    pub async fn acquire(&self, kind: RequestKind) -> Duration {
+        let wait = self.bucket(kind).reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        if let Some(observer) = self.observer.as_ref() {
+            observer(kind, wait);
+        }
+        wait
+    }
+
+    // 该种类请求累计的等待时间
+    pub fn total_waited(&self, kind: RequestKind) -> Duration {
+        self.bucket(kind).stats().0
+    }
+
+    // 该种类请求累计获取的令牌数
+    pub fn total_acquired(&self, kind: RequestKind) -> u64 {
+        self.bucket(kind).stats().1
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: Duration, expected_ms: u64) -> bool {
+        actual.abs_diff(Duration::from_millis(expected_ms)) < Duration::from_millis(5)
+    }
+
+    #[test]
+    fn burst_then_queue() {
+        let bucket = TokenBucket::new(RateLimit::new(2, 10.0));
+        assert!(bucket.reserve().is_zero());
+        assert!(bucket.reserve().is_zero());
+        // 令牌用完后依次排队，每个令牌间隔 1/per_second
+        assert!(close(bucket.reserve(), 100));
+        assert!(close(bucket.reserve(), 200));
+        let (waited, acquired) = bucket.stats();
+        assert!(close(waited, 300));
+        assert_eq!(acquired, 4);
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let bucket = TokenBucket::new(RateLimit::new(2, 10.0));
+        for _ in 0..4 {
+            let _ = bucket.reserve();
+        }
+        // 过了 10 秒，欠下的 2 个令牌补上后最多积攒 burst 个
+        bucket.state.lock().unwrap().last -= Duration::from_secs(10);
+        assert!(bucket.reserve().is_zero());
+        assert!(bucket.reserve().is_zero());
+        assert!(close(bucket.reserve(), 100));
+    }
+
+    #[test]
+    #[should_panic(expected = "per_second")]
+    fn zero_rate_is_rejected() {
+        RateLimit::new(5, 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "per_second")]
+    fn zero_rate_in_fields_is_rejected() {
+        let limit = RateLimit { burst: 5, per_second: 0.0 };
+        RateLimiter::new(limit, limit, limit);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::forum::{Thread, ThreadReply};
+
+
+/// 两次读取之间串发生的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadChange {
+    /// 被 Sage 或取消 Sage
+    Sage(bool),
+    /// 被隐藏或取消隐藏
+    Hidden(bool),
+    /// 串已被删除（接口返回“该串不存在”）
+    Deleted,
+}
+
+
+/// 增量刷新串的结果
+#[derive(Debug, Clone)]
+pub struct ThreadUpdate {
+    /// 最新的主串信息（不含回复），串被删除时为 None
+    pub head: Option<Thread>,
+    /// 上次读取之后的新回复，按串号排序
+    pub new_replies: Vec<ThreadReply>,
+    pub changes: Vec<ThreadChange>,
+}
+
+impl ThreadUpdate {
+
+    pub fn is_deleted(&self) -> bool {
+        self.changes.contains(&ThreadChange::Deleted)
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.new_replies.is_empty() || !self.changes.is_empty()
+    }
+
+    // 把新回复和最新的主串信息合并到之前读取的串中
+    pub fn apply_to(&self, thread: &mut Thread) {
+        if let Some(head) = &self.head {
+            let replies = thread.replies.take();
+            *thread = head.clone();
+            thread.replies = replies;
+        }
+        thread.replies.get_or_insert_with(Vec::new).extend(self.new_replies.iter().cloned());
+    }
+}
+
+
+// 去掉跨页重复的回复（读取期间翻页所致），按串号排序
+pub(crate) fn dedup_replies(replies: &mut Vec<ThreadReply>) {
+    let mut seen = std::collections::HashSet::new();
+    replies.retain(|reply| seen.insert(*reply.tid));
+    replies.sort_by_key(|reply| *reply.tid);
+}
+
+
+// 比较两次读取的主串，得出 Sage、隐藏状态的变化
+pub(crate) fn diff_head(previous: &Thread, current: &Thread) -> Vec<ThreadChange> {
+    let flag = |value: Option<crate::forum::SNBool>| value.is_some_and(|b| b.into_inner());
+    let mut changes = Vec::new();
+    if flag(previous.sage) != flag(current.sage) {
+        changes.push(ThreadChange::Sage(flag(current.sage)));
+    }
+    if flag(previous.hide) != flag(current.hide) {
+        changes.push(ThreadChange::Hidden(flag(current.hide)));
+    }
+    changes
+}
+
+// 之前读取到的最后一条回复的串号，没有回复时为主串串号
+pub(crate) fn last_known_id(thread: &Thread) -> i64 {
+    thread.replies.iter()
+        .flatten()
+        .filter(|reply| !reply.is_tips())
+        .map(|reply| *reply.tid)
+        .max()
+        .unwrap_or(*thread.tid)
+}
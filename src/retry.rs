@@ -0,0 +1,131 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::Error;
+use crate::transport::Method;
+
+
+type RetryPredicate = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+
+/// 请求失败后的重试策略：指数退避 + 随机抖动。
+/// 默认只重试 GET 请求，POST（发串、回复、订阅）不重试，以免重复发串。
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    retry_posts: bool,
+    retryable: RetryPredicate,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_posts: false,
+            retryable: Arc::new(is_transient),
+        }
+    }
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .field("retry_posts", &self.retry_posts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 不重试
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    // 包含第一次请求在内的最大尝试次数，最小为1
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    // 第一次重试前的等待时间，之后每次翻倍
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    // 单次等待时间的上限
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    // 随机抖动比例（0.0~1.0），实际等待时间在 [delay*(1-jitter), delay] 之间
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    // 是否也重试POST请求，默认不重试，开启后可能导致重复发串
+    pub fn retry_posts(mut self, retry_posts: bool) -> Self {
+        self.retry_posts = retry_posts;
+        self
+    }
+
+    // 自定义哪些错误可以重试，默认为 [`is_transient`]
+    pub fn retryable<F>(mut self, f: F) -> Self
+        where F: Fn(&Error) -> bool + Send + Sync + 'static
+    {
+        self.retryable = Arc::new(f);
+        self
+    }
+
+    // 该方法的请求是否可能被重试
+    pub fn allows(&self, method: Method) -> bool {
+        self.max_attempts > 1 && (method == Method::Get || self.retry_posts)
+    }
+
+    // 第 attempt 次（从1开始）请求失败后是否应再次尝试
+    pub fn should_retry(&self, method: Method, error: &Error, attempt: u32) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        if method == Method::Post && !self.retry_posts {
+            return false;
+        }
+        (self.retryable)(error)
+    }
+
+    // 第 attempt 次（从1开始）请求失败后的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay.saturating_mul(1u32 << exp).min(self.max_delay);
+        let factor = 1.0 - self.jitter * fastrand::f64();
+        delay.mul_f64(factor)
+    }
+}
+
+
+// 默认的可重试判断：连接失败、超时、429 以及 5xx
+pub fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Transport(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        Error::Status { status, .. } => *status == 429 || (500..600).contains(status),
+        _ => false,
+    }
+}
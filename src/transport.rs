@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{Error, Result};
+use crate::attachment::{Attachment, AttachmentSource};
+
+
+/// ApiClient 与网络之间的传输层。默认使用 [`ReqwestTransport`]，
+/// 测试时可替换为 [`MockTransport`] 或 [`ReplayTransport`] 离线运行。
+#[async_trait]
+pub trait Transport: Send + Sync + Debug {
+    async fn send(&self, request: Request) -> Result<Response>;
+
+    // 状态码为 2xx 时先以响应头调用 sink.start，再把响应体边接收边写入 sink，
+    // 返回的 Response 不含响应体；其它状态码不写入 sink，响应体照常放在 Response 中。
+    // 传输中断时已接收的部分已经写入，可用于断点续传
+    async fn send_streaming(&self, request: Request, sink: &mut dyn StreamSink) -> Result<Response> {
+        let mut response = self.send(request).await?;
+        if response.is_success() {
+            sink.start(&response).await?;
+            sink.write_all(&response.body).await?;
+            sink.flush().await?;
+            response.body.clear();
+        }
+        Ok(response)
+    }
+}
+
+// 便于在交给 ApiClient 之后仍能访问传输层，例如读取 MockTransport 记录的请求
+#[async_trait]
+impl<T> Transport for Arc<T>
+    where T: Transport + ?Sized
+{
+    async fn send(&self, request: Request) -> Result<Response> {
+        (**self).send(request).await
+    }
+
+    async fn send_streaming(&self, request: Request, sink: &mut dyn StreamSink) -> Result<Response> {
+        (**self).send_streaming(request, sink).await
+    }
+}
+
+
+/// send_streaming 写入响应体的目标
+#[async_trait]
+pub trait StreamSink: AsyncWrite + Send + Unpin {
+
+    // 收到 2xx 响应、写入响应体之前调用，head 不含响应体。
+    // 例如断点续传时服务器忽略了 Range 返回 200，需要先清空已写入的部分
+    async fn start(&mut self, head: &Response) -> std::io::Result<()> {
+        let _ = head;
+        Ok(())
+    }
+}
+
+impl StreamSink for Vec<u8> {}
+
+impl StreamSink for tokio::fs::File {}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+        }
+    }
+}
+
+
+/// 与具体HTTP库无关的请求
+#[derive(Debug)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Request {
+
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Body::Empty,
+        }
+    }
+
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(Method::Get, url)
+    }
+
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(Method::Post, url)
+    }
+
+    pub fn query(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: Body) -> Self {
+        self.body = body;
+        self
+    }
+
+    // 复制请求，请求体中含有流来源的附图时无法复制
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            method: self.method,
+            url: self.url.clone(),
+            query: self.query.clone(),
+            headers: self.headers.clone(),
+            body: self.body.try_clone()?,
+        })
+    }
+
+    // 将请求体中的附图全部读入内存，之后即可复制
+    pub async fn buffered(self) -> Result<Self> {
+        let body = match self.body {
+            Body::Multipart(parts) => {
+                let mut buffered = Vec::with_capacity(parts.len());
+                for part in parts {
+                    buffered.push(match part {
+                        Part::File { name, attachment } => Part::File { name, attachment: attachment.buffered().await? },
+                        text => text,
+                    });
+                }
+                Body::Multipart(buffered)
+            }
+            body => body,
+        };
+        Ok(Self { body, ..self })
+    }
+
+    // URL中的路径部分（不含开头的斜杠），如 "api/thread"，用作接口的标识
+    pub fn endpoint(&self) -> String {
+        match reqwest::Url::parse(&self.url) {
+            Ok(url) => url.path().trim_start_matches('/').to_string(),
+            Err(_) => self.url.clone(),
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    /// application/x-www-form-urlencoded
+    Form(Vec<(String, String)>),
+    /// multipart/form-data
+    Multipart(Vec<Part>),
+}
+
+impl Body {
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(match self {
+            Body::Empty => Body::Empty,
+            Body::Form(fields) => Body::Form(fields.clone()),
+            Body::Multipart(parts) => Body::Multipart(parts.iter().map(Part::try_clone).collect::<Option<_>>()?),
+        })
+    }
+}
+
+
+/// multipart 表单中的一项
+#[derive(Debug)]
+pub enum Part {
+    Text {
+        name: String,
+        value: String,
+    },
+    File {
+        name: String,
+        attachment: Attachment,
+    },
+}
+
+impl Part {
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(match self {
+            Part::Text { name, value } => Part::Text { name: name.clone(), value: value.clone() },
+            Part::File { name, attachment } => Part::File { name: name.clone(), attachment: attachment.try_clone()? },
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    pub status: u16,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    // 按名称（不区分大小写）查找响应头
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+
+
+/// 基于 reqwest 的默认传输层
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl ReqwestTransport {
+
+    async fn request(&self, request: Request) -> Result<reqwest::Response> {
+        let mut builder = match request.method {
+            Method::Get => self.client.get(&request.url),
+            Method::Post => self.client.post(&request.url),
+        };
+        if !request.query.is_empty() {
+            builder = builder.query(&request.query);
+        }
+        for (key, value) in &request.headers {
+            builder = builder.header(key, value);
+        }
+        builder = match request.body {
+            Body::Empty => builder,
+            Body::Form(fields) => builder.form(&fields),
+            Body::Multipart(parts) => {
+                let mut form = reqwest::multipart::Form::new();
+                for part in parts {
+                    form = match part {
+                        Part::Text { name, value } => form.text(name, value),
+                        Part::File { name, attachment } => form.part(name, file_part(attachment).await?),
+                    };
+                }
+                builder.multipart(form)
+            }
+        };
+        Ok(builder.send().await?)
+    }
+
+    // 除响应体以外的部分
+    fn head(response: &reqwest::Response) -> Response {
+        let headers = response.headers().iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+        Response {
+            status: response.status().as_u16(),
+            url: response.url().to_string(),
+            headers,
+            body: Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let response = self.request(request).await?;
+        let mut head = Self::head(&response);
+        head.body = response.bytes().await?.to_vec();
+        Ok(head)
+    }
+
+    async fn send_streaming(&self, request: Request, sink: &mut dyn StreamSink) -> Result<Response> {
+        let mut response = self.request(request).await?;
+        let mut head = Self::head(&response);
+        if !head.is_success() {
+            head.body = response.bytes().await?.to_vec();
+            return Ok(head);
+        }
+        sink.start(&head).await?;
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => sink.write_all(&chunk).await?,
+                Ok(None) => break,
+                Err(e) => {
+                    sink.flush().await.ok();
+                    return Err(e.into());
+                }
+            }
+        }
+        sink.flush().await?;
+        Ok(head)
+    }
+}
+
+
+// 将附图转换为 reqwest 的 multipart 部分，文件和读取流都以流的形式发送
+async fn file_part(attachment: Attachment) -> Result<reqwest::multipart::Part> {
+    use reqwest::multipart::Part as ReqwestPart;
+    use tokio_util::io::ReaderStream;
+
+    let (file_name, mime, source) = attachment.into_source();
+    let part = match source {
+        AttachmentSource::Bytes(data) => {
+            let length = data.len() as u64;
+            ReqwestPart::stream_with_length(reqwest::Body::from(data), length)
+        }
+        AttachmentSource::File(path) => {
+            let file = tokio::fs::File::open(&path).await?;
+            let length = file.metadata().await?.len();
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+            ReqwestPart::stream_with_length(body, length)
+        }
+        AttachmentSource::Reader { reader, length } => {
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(reader));
+            match length {
+                Some(length) => ReqwestPart::stream_with_length(body, length),
+                None => ReqwestPart::stream(body),
+            }
+        }
+    };
+    Ok(part.file_name(file_name).mime_str(&mime)?)
+}
+
+
+/// 内存中的模拟传输层，按接口路径返回预设的响应，并记录收到的所有请求
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    routes: Mutex<HashMap<String, Vec<MockRoute>>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+#[derive(Debug, Clone)]
+struct MockRoute {
+    query: Vec<(String, String)>,
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl MockTransport {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 为接口（如 "api/thread"）设置返回的JSON，不区分查询参数
+    pub fn on(&self, endpoint: &str, json: serde_json::Value) -> &Self {
+        self.on_query(endpoint, &[], json)
+    }
+
+    // 仅当请求包含全部给定查询参数时返回此JSON，优先于参数更少的设置
+    pub fn on_query(&self, endpoint: &str, query: &[(&str, &str)], json: serde_json::Value) -> &Self {
+        self.on_raw(endpoint, query, 200, json.to_string().into_bytes())
+    }
+
+    // 设置任意状态码和响应体，可用于模拟发串结果页面和服务器错误
+    pub fn on_raw(&self, endpoint: &str, query: &[(&str, &str)], status: u16, body: impl Into<Vec<u8>>) -> &Self {
+        let route = MockRoute {
+            query: query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            status,
+            body: body.into(),
+        };
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let list = routes.entry(endpoint.trim_start_matches('/').to_string()).or_default();
+        list.retain(|r| r.query != route.query);
+        list.push(route);
+        list.sort_by_key(|r| std::cmp::Reverse(r.query.len()));
+        self
+    }
+
+    // 到目前为止收到的所有请求，附图已读入内存
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter_map(Request::try_clone)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let endpoint = request.endpoint();
+        let route = {
+            let routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+            routes.get(&endpoint).and_then(|list| {
+                list.iter()
+                    .find(|r| r.query.iter().all(|q| request.query.contains(q)))
+                    .cloned()
+            })
+        };
+        let url = request.url.clone();
+        let request = request.buffered().await?;
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).push(request);
+        match route {
+            Some(route) => Ok(Response {
+                status: route.status,
+                url,
+                headers: Vec::new(),
+                body: route.body,
+            }),
+            None => Err(Error::FixtureMissing(endpoint)),
+        }
+    }
+}
+
+
+
+/// 从磁盘上的录制文件回放响应。
+/// 每个请求对应 `{dir}/{接口路径}/{方法}_{参数}.json`（状态码和响应头）
+/// 与同名的 `.body` 文件（原始响应体）。
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    dir: PathBuf,
+}
+
+impl ReplayTransport {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let key = fixture_key(&request);
+        let meta_path = fixture_path(&self.dir, &key, "json");
+        let meta = match tokio::fs::read(&meta_path).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::FixtureMissing(key));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let meta: FixtureMeta = serde_json::from_slice(&meta).map_err(|e| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid fixture {}: {e}", meta_path.display()),
+        ))?;
+        let body = tokio::fs::read(fixture_path(&self.dir, &key, "body")).await?;
+        Ok(Response {
+            status: meta.status,
+            url: request.url,
+            headers: meta.headers,
+            body,
+        })
+    }
+}
+
+
+/// 录制模式：将请求交给内部传输层，并把每一对请求/响应按 [`ReplayTransport`]
+/// 的格式写入录制目录，之后可用 ReplayTransport 原样回放。
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    dir: PathBuf,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: impl Transport + 'static, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            inner: Box::new(inner),
+            dir: dir.into(),
+        }
+    }
+
+    pub(crate) fn from_boxed(inner: Box<dyn Transport>, dir: PathBuf) -> Self {
+        Self { inner, dir }
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let key = fixture_key(&request);
+        let mut meta = FixtureMeta {
+            method: request.method,
+            endpoint: request.endpoint(),
+            query: request.query.clone(),
+            status: 0,
+            headers: Vec::new(),
+        };
+        let response = self.inner.send(request).await?;
+        meta.status = response.status;
+        meta.headers = response.headers.clone();
+
+        let meta_path = fixture_path(&self.dir, &key, "json");
+        if let Some(parent) = meta_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let meta = serde_json::to_vec_pretty(&meta).map_err(std::io::Error::other)?;
+        tokio::fs::write(&meta_path, meta).await?;
+        tokio::fs::write(fixture_path(&self.dir, &key, "body"), &response.body).await?;
+        Ok(response)
+    }
+}
+
+
+// 录制文件中除响应体以外的内容
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct FixtureMeta {
+    pub method: Method,
+    pub endpoint: String,
+    pub query: Vec<(String, String)>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+
+// 由接口路径、请求方法、排序后的查询参数和表单字段组成的录制文件名（不含扩展名）。
+// 表单字段让 addFeed、delFeed 等参数在请求体中的请求各自对应不同的文件；
+// 文件名过长（如发串正文）时截断并附上完整名称的哈希
+pub(crate) fn fixture_key(request: &Request) -> String {
+    let mut query = request.query.clone();
+    query.sort();
+    let mut fields = match &request.body {
+        Body::Empty => Vec::new(),
+        Body::Form(fields) => fields.clone(),
+        Body::Multipart(parts) => parts.iter()
+            .map(|part| match part {
+                Part::Text { name, value } => (name.clone(), value.clone()),
+                Part::File { name, attachment } => (name.clone(), attachment.file_name.clone()),
+            })
+            .collect(),
+    };
+    fields.sort();
+    let mut name = request.method.as_str().to_string();
+    for (k, v) in query.iter().chain(&fields) {
+        name.push('_');
+        name.push_str(&sanitize(k));
+        name.push('-');
+        name.push_str(&sanitize(v));
+    }
+    if name.len() > MAX_FIXTURE_NAME {
+        let hash = Sha256::digest(name.as_bytes())
+            .iter()
+            .take(8)
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        name.truncate(MAX_FIXTURE_NAME - hash.len() - 1);
+        name.push('_');
+        name.push_str(&hash);
+    }
+    let endpoint = request.endpoint()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(sanitize)
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("{endpoint}/{name}")
+}
+
+// 录制文件名（不含扩展名）的最大长度，sanitize 之后只含 ASCII 字符
+const MAX_FIXTURE_NAME: usize = 120;
+
+pub(crate) fn fixture_path(dir: &Path, key: &str, ext: &str) -> PathBuf {
+    dir.join(format!("{key}.{ext}"))
+}
+
+// 只保留文件名中安全的字符
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
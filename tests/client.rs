@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+use xdnmb_rs::cookie::{CookieJar, UserCookie};
+use xdnmb_rs::ratelimit::{RateLimit, RateLimiter, RequestKind};
+use xdnmb_rs::transport::{Method, MockTransport, Request, Response, Transport};
+use xdnmb_rs::{ApiClient, ApiError, Error, Post, Result, RetryPolicy};
+
+
+fn client(transport: impl Transport + 'static, retry: RetryPolicy) -> ApiClient {
+    ApiClient::builder()
+        .transport(transport)
+        .retry_policy(retry)
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap()
+}
+
+fn fast_retry(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy::new().max_attempts(max_attempts).base_delay(Duration::from_millis(1)).jitter(0.0)
+}
+
+fn thread_json(id: i64) -> serde_json::Value {
+    json!({
+        "id": id, "fid": 4, "ReplyCount": 0, "img": "", "ext": "", "now": "2022-06-18(六)12:00:00",
+        "user_hash": "abcdefg", "name": "无名氏", "title": "无标题", "content": "正文",
+        "sage": 0, "admin": 0, "Hide": 0, "Replies": [],
+    })
+}
+
+
+// 前 failures 次请求返回 503，之后交给内部的模拟传输层
+#[derive(Debug)]
+struct Flaky {
+    inner: MockTransport,
+    failures: usize,
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl Transport for Flaky {
+    async fn send(&self, request: Request) -> Result<Response> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Ok(Response { status: 503, url: request.url, headers: Vec::new(), body: b"busy".to_vec() });
+        }
+        self.inner.send(request).await
+    }
+}
+
+
+#[tokio::test]
+async fn api_error_payloads() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_query("api/thread", &[("id", "1")], json!("该串不存在"));
+    mock.on_query("api/showf", &[("id", "999")], json!({"success": false, "error": "版块不存在"}));
+    let client = client(mock.clone(), RetryPolicy::none());
+
+    match client.get_thread_page(1, 1, false).await {
+        Err(Error::Api(ApiError::ThreadNotFound(message))) => assert_eq!(message, "该串不存在"),
+        other => panic!("unexpected {other:?}"),
+    }
+    match client.get_threads_from_forum(999, 1).await {
+        Err(Error::Api(ApiError::ForumNotFound(message))) => assert_eq!(message, "版块不存在"),
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn decode_error_reports_field_path() {
+    let mock = Arc::new(MockTransport::new());
+    let mut thread = thread_json(50000000);
+    thread["Replies"] = json!([thread_json(50000001), {"id": "not a number"}]);
+    mock.on("api/thread", thread);
+    mock.on_raw("api/ref", &[], 200, "<html>");
+    let client = client(mock.clone(), RetryPolicy::none());
+
+    match client.get_thread_page(50000000, 1, false).await {
+        Err(Error::Decode { path, body, .. }) => {
+            assert_eq!(path, "Replies[1].id");
+            assert!(body.contains("not a number"));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    match client.get_reply(1).await {
+        Err(Error::Decode { path, body, .. }) => assert_eq!((path.as_str(), body.as_str()), (".", "<html>")),
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let inner = MockTransport::new();
+    inner.on("api/thread", thread_json(50000000));
+    let flaky = Arc::new(Flaky { inner, failures: 2, calls: AtomicUsize::new(0) });
+    let thread = client(flaky.clone(), fast_retry(3)).get_thread_page(50000000, 1, false).await.unwrap();
+    assert_eq!(*thread.tid, 50000000);
+    assert_eq!(flaky.calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("api/thread", &[], 502, "Bad Gateway");
+    match client(mock.clone(), fast_retry(3)).get_thread_page(50000000, 1, false).await {
+        Err(Error::Status { status, body, .. }) => assert_eq!((status, body.as_str()), (502, "Bad Gateway")),
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("api/thread", &[], 404, "Not Found");
+    let result = client(mock.clone(), fast_retry(3)).get_thread_page(50000000, 1, false).await;
+    assert!(matches!(result, Err(Error::Status { status: 404, .. })));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn posts_are_not_retried_by_default() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("Home/Forum/doPostThread.html", &[], 503, "busy");
+    let result = client(mock.clone(), fast_retry(3))
+        .send_post(Post::new_thread(4).content("hello"))
+        .await;
+    assert!(matches!(result, Err(Error::Status { status: 503, .. })));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn post_form_fields() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw(
+        "Home/Forum/doPostThread.html", &[], 200,
+        r#"<h1>:)</h1><p class="success">发帖成功</p><a id="href" href="/f/综合版1">跳转</a>"#,
+    );
+    let client = client(mock.clone(), RetryPolicy::none());
+    let outcome = client
+        .send_post(Post::new_thread(4).title("标题").content("正文"))
+        .await
+        .unwrap();
+    assert_eq!(outcome.message, "发帖成功");
+
+    let requests = mock.requests();
+    let request = requests.last().unwrap();
+    assert_eq!(request.method, Method::Post);
+    let form = format!("{:?}", request.body);
+    for field in ["\"fid\"", "\"4\"", "\"title\"", "\"标题\"", "\"content\"", "\"正文\""] {
+        assert!(form.contains(field), "{field} missing in {form}");
+    }
+}
+
+#[tokio::test]
+async fn probe_marks_server_errors_unhealthy() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on("api/getCDNPath", json!([{"url": "https://image.example/", "rate": 1.0}]));
+    mock.on_raw("", &[], 503, "busy");
+    let client = client(mock.clone(), RetryPolicy::none());
+
+    let results = client.probe_cdn_latency().await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].1.is_none());
+
+    mock.on_raw("", &[], 404, "not found");
+    let results = client.probe_cdn_latency().await.unwrap();
+    assert!(results[0].1.is_some());
+    assert_eq!(client.cdn_selector().select().unwrap().url, "https://image.example/");
+}
+
+#[tokio::test]
+async fn rate_limit_is_shared_by_clones() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on("api/thread", thread_json(1));
+    let waits = Arc::new(Mutex::new(Vec::new()));
+    let limiter = {
+        let waits = waits.clone();
+        let reads = RateLimit::new(1, 20.0);
+        RateLimiter::new(reads, RateLimit::new(1, 1.0), RateLimit::new(1, 1.0))
+            .on_wait(move |kind, wait| waits.lock().unwrap().push((kind, wait)))
+    };
+    let client = ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .rate_limiter(Arc::new(limiter))
+        .no_cooldown()
+        .build()
+        .unwrap();
+    let other = client.clone();
+
+    // 两个克隆共用 1 个令牌的额度，后一个请求需要等待约 50ms
+    client.get_thread_page(1, 1, false).await.unwrap();
+    other.get_thread_page(1, 1, false).await.unwrap();
+    assert!(Arc::ptr_eq(client.rate_limiter().unwrap(), other.rate_limiter().unwrap()));
+    assert_eq!(client.rate_limiter().unwrap().total_acquired(RequestKind::Read), 2);
+    assert_eq!(client.rate_limiter().unwrap().total_acquired(RequestKind::Post), 0);
+
+    let waits = waits.lock().unwrap().clone();
+    assert_eq!(waits.len(), 2);
+    assert_eq!(waits[0], (RequestKind::Read, Duration::ZERO));
+    assert_eq!(waits[1].0, RequestKind::Read);
+    assert!(waits[1].1 > Duration::from_millis(40), "{waits:?}");
+    assert_eq!(other.rate_limiter().unwrap().total_waited(RequestKind::Read), waits[1].1);
+}
+
+#[tokio::test]
+async fn cookie_header_on_reads_and_posts() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on("api/thread", thread_json(1));
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, r#"<h1>:)</h1><p class="success">回复成功</p><a id="href" href="/t/1">跳转</a>"#);
+    let client = ApiClient::builder()
+        .transport(mock.clone())
+        .auth_cookie(UserCookie::new("饼干", "hash", "abc%2Fdef"))
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap();
+
+    client.get_thread_page(1, 1, false).await.unwrap();
+    client.send_post(Post::reply(1).content("正文")).await.unwrap();
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        let cookies: Vec<_> = request.headers.iter().filter(|(k, _)| k == "cookie").collect();
+        assert_eq!(cookies, [&("cookie".to_string(), "userhash=abc%2Fdef".to_string())], "{}", request.url);
+    }
+}
+
+#[tokio::test]
+async fn auth_cookie_replaces_shared_jar_cookie() {
+    let jar = CookieJar::new(Some(UserCookie::new("旧饼干", "old", "old")));
+    let client = ApiClient::builder()
+        .transport(MockTransport::new())
+        .cookie_jar(jar.clone())
+        .auth_cookie(UserCookie::new("新饼干", "new", "new"))
+        .build()
+        .unwrap();
+    assert_eq!(jar.get().unwrap().value, "new");
+    assert_eq!(client.cookie_jar().header_value().as_deref(), Some("userhash=new"));
+
+    // 未设置 auth_cookie 时沿用 CookieJar 中的饼干
+    let other = ApiClient::builder().transport(MockTransport::new()).cookie_jar(jar.clone()).build().unwrap();
+    assert_eq!(other.cookie_jar().header_value().as_deref(), Some("userhash=new"));
+}
+
+#[tokio::test]
+async fn custom_base_urls() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on("api/thread", thread_json(1));
+    mock.on_raw("nmb/Home/Forum/doReplyThread.html", &[], 200, r#"<h1>:)</h1><p class="success">回复成功</p><a id="href" href="/t/1">跳转</a>"#);
+    let client = ApiClient::builder()
+        .transport(mock.clone())
+        .base_url("https://api.example.com/")
+        .post_base_url("https://www.example.com/nmb//")
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap();
+
+    client.get_thread_page(1, 1, false).await.unwrap();
+    client.send_post(Post::reply(1).content("正文")).await.unwrap();
+    let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+    assert_eq!(urls, [
+        "https://api.example.com/api/thread",
+        "https://www.example.com/nmb/Home/Forum/doReplyThread.html",
+    ]);
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use xdnmb_rs::cooldown::CooldownMode;
+use xdnmb_rs::transport::MockTransport;
+use xdnmb_rs::{ApiClient, Error, Post, RetryPolicy};
+
+
+const SUCCESS_PAGE: &str = r#"<h1>:)</h1><p class="success">回复成功</p><a id="href" href="/t/50000000">跳转</a>"#;
+
+// 版块 4 的发串间隔为 30 秒，api/ref 与录制的真实数据一样不带 fid
+fn forum_mock() -> Arc<MockTransport> {
+    let mock = Arc::new(MockTransport::new());
+    mock.on("api/getForumList", json!([{
+        "id": "4", "sort": "1", "name": "综合", "status": "n",
+        "forums": [{"id": "4", "name": "综合版1", "msg": "", "interval": "30"}],
+    }]));
+    mock.on_query("api/ref", &[("id", "50000000")], json!({
+        "id": 50000000, "img": "", "ext": "", "now": "", "user_hash": "abcdefg", "content": "",
+    }));
+    mock.on_query("api/thread", &[("id", "50000000")], json!({
+        "id": 50000000, "fid": 4, "ReplyCount": 0, "img": "", "ext": "", "now": "",
+        "user_hash": "abcdefg", "content": "", "Replies": [],
+    }));
+    mock
+}
+
+fn client(mock: &Arc<MockTransport>, mode: CooldownMode) -> ApiClient {
+    ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .cooldown_mode(mode)
+        .build()
+        .unwrap()
+}
+
+fn post_requests(mock: &MockTransport) -> usize {
+    mock.requests().iter().filter(|r| r.url.ends_with("doReplyThread.html")).count()
+}
+
+
+#[tokio::test]
+async fn reply_cooldown_uses_thread_forum() {
+    let mock = forum_mock();
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+    let client = client(&mock, CooldownMode::Error);
+
+    client.send_post(Post::reply(50000000).content("1")).await.unwrap();
+    match client.send_post(Post::reply(50000000).content("2")).await {
+        Err(Error::Cooldown { fid, retry_after }) => {
+            assert_eq!(fid, 4);
+            assert!(retry_after > Duration::from_secs(25));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert_eq!(post_requests(&mock), 1);
+    assert_eq!(client.cooldown().unwrap().thread_forum(50000000), Some(4));
+}
+
+#[tokio::test]
+async fn failed_post_does_not_start_cooldown() {
+    let mock = forum_mock();
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, r#"<h1>:(</h1><p class="error">没有饼干不能发串哦</p>"#);
+    let client = client(&mock, CooldownMode::Error);
+    assert!(matches!(
+        client.send_post(Post::reply(50000000).content("1")).await,
+        Err(Error::PostRejected(_)),
+    ));
+
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+    client.send_post(Post::reply(50000000).content("2")).await.unwrap();
+    assert_eq!(post_requests(&mock), 2);
+}
+
+#[tokio::test]
+async fn cancelled_post_releases_reservation() {
+    let mock = forum_mock();
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+    let client = client(&mock, CooldownMode::Wait);
+    client.send_post(Post::reply(50000000).content("1")).await.unwrap();
+
+    // 第二次发串需等待约 30 秒，中途放弃
+    let waiting = client.send_post(Post::reply(50000000).content("2"));
+    assert!(tokio::time::timeout(Duration::from_millis(50), waiting).await.is_err());
+    assert_eq!(post_requests(&mock), 1);
+
+    // 放弃的预定已归还，剩余时间仍只是第一次发串的冷却
+    let remaining = client.cooldown().unwrap().remaining(4, "");
+    assert!(remaining <= Duration::from_secs(30), "{remaining:?}");
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use xdnmb_rs::cdnpath::ImageVariant;
+use xdnmb_rs::harvest::{HarvestManifest, HarvestOptions};
+use xdnmb_rs::transport::{MockTransport, Request, Response, StreamSink, Transport};
+use xdnmb_rs::{ApiClient, Result, RetryPolicy};
+
+
+fn post(id: i64, img: &str) -> serde_json::Value {
+    json!({"id": id, "fid": 4, "user_hash": "abcdefg", "now": "", "content": "", "img": img, "ext": if img.is_empty() { "" } else { ".png" }})
+}
+
+// 接口请求交给 MockTransport，图片请求返回一张 PNG，img 为 broken 的返回 404。
+// 记录请求过的图片地址和同时进行的最大下载数
+#[derive(Debug, Default)]
+struct HarvestStub {
+    api: MockTransport,
+    images: Mutex<Vec<String>>,
+    active: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+#[async_trait]
+impl Transport for HarvestStub {
+    async fn send(&self, request: Request) -> Result<Response> {
+        self.api.send(request).await
+    }
+
+    async fn send_streaming(&self, request: Request, sink: &mut dyn StreamSink) -> Result<Response> {
+        self.images.lock().unwrap().push(request.url.clone());
+        let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(active, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        self.active.fetch_sub(1, Ordering::SeqCst);
+
+        let status = if request.url.contains("broken") { 404 } else { 200 };
+        let response = Response { status, url: request.url.clone(), headers: Vec::new(), body: Vec::new() };
+        if status == 200 {
+            sink.start(&response).await?;
+            sink.write_all(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0]).await?;
+        }
+        Ok(response)
+    }
+}
+
+// 串 100：主串和 5 条回复带图（其中 102 的图下载会失败），103 没有图；Po 只回复了 101
+fn stub() -> Arc<HarvestStub> {
+    let stub = Arc::new(HarvestStub::default());
+    stub.api.on("api/getCDNPath", json!([{"url": "https://cdn.example/", "rate": 1.0}]));
+    let replies = [post(101, "2022/a"), post(102, "broken"), post(103, ""), post(104, "2022/c"), post(105, "2022/d"), post(106, "2022/e")];
+    let mut thread = post(100, "2022/head");
+    thread["ReplyCount"] = json!(replies.len());
+    thread["Replies"] = json!(replies);
+    stub.api.on_query("api/thread", &[("id", "100")], thread);
+    let mut po = post(100, "2022/head");
+    po["ReplyCount"] = json!(6);
+    po["Replies"] = json!([post(101, "2022/a")]);
+    stub.api.on_query("api/po", &[("id", "100")], po);
+    stub
+}
+
+fn client(stub: &Arc<HarvestStub>, name: &str) -> (ApiClient, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-harvest-{}-{name}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let client = ApiClient::builder()
+        .transport(stub.clone())
+        .image_cache_dir(dir.join("cache"))
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap();
+    (client, dir)
+}
+
+fn entry_ids(manifest: &HarvestManifest) -> Vec<i64> {
+    manifest.entries.iter().map(|e| e.post_id).collect()
+}
+
+
+#[tokio::test]
+async fn harvest_all_images_with_failures() {
+    let stub = stub();
+    let (client, dir) = client(&stub, "all");
+    let options = HarvestOptions::new().manifest(dir.join("manifest.json"));
+    let manifest = client.harvest_thread_images(100, &options, |_| {}).await.unwrap();
+
+    // 单张失败不影响其余附图
+    assert_eq!(entry_ids(&manifest), [100, 101, 104, 105, 106]);
+    assert_eq!(manifest.failures.len(), 1);
+    assert_eq!(manifest.failures[0].post_id, 102);
+    assert!(manifest.failures[0].error.contains("404"), "{}", manifest.failures[0].error);
+    assert!(manifest.entries.iter().all(|e| e.path.exists()));
+    assert!(stub.images.lock().unwrap().iter().all(|url| url.starts_with("https://cdn.example/image/")));
+
+    // 写入的清单与返回值一致
+    let written: HarvestManifest = serde_json::from_slice(&std::fs::read(dir.join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(written.tid, 100);
+    assert_eq!(written.variant, "image");
+    assert_eq!(entry_ids(&written), entry_ids(&manifest));
+    assert_eq!(written.entries[1].img, "2022/a");
+    assert_eq!(written.entries[1].ext, ".png");
+    assert_eq!(written.entries[1].path, manifest.entries[1].path);
+    assert_eq!(written.failures[0].post_id, 102);
+}
+
+#[tokio::test]
+async fn harvest_po_thumbnails() {
+    let stub = stub();
+    let (client, _dir) = client(&stub, "po");
+    let options = HarvestOptions::new().po_only(true).variant(ImageVariant::Thumb);
+    let manifest = client.harvest_thread_images(100, &options, |_| {}).await.unwrap();
+
+    assert_eq!(manifest.variant, "thumb");
+    assert_eq!(entry_ids(&manifest), [100, 101]);
+    let requests = stub.api.requests();
+    assert!(requests.iter().any(|r| r.url.ends_with("api/po")));
+    assert!(!requests.iter().any(|r| r.url.ends_with("api/thread")));
+    let images = stub.images.lock().unwrap().clone();
+    assert_eq!(images.len(), 2);
+    assert!(images.iter().all(|url| url.starts_with("https://cdn.example/thumb/")), "{images:?}");
+}
+
+#[tokio::test]
+async fn harvest_respects_concurrency_and_reports_progress() {
+    let stub = stub();
+    let (client, _dir) = client(&stub, "progress");
+    let options = HarvestOptions::new().concurrency(2);
+    let mut progress = Vec::new();
+    client.harvest_thread_images(100, &options, |p| {
+        progress.push((p.completed, p.total, p.post_id, p.result.is_ok()));
+    }).await.unwrap();
+
+    assert_eq!(stub.peak.load(Ordering::SeqCst), 2);
+    assert_eq!(progress.iter().map(|p| (p.0, p.1)).collect::<Vec<_>>(), (1..=6).map(|n| (n, 6)).collect::<Vec<_>>());
+    let ids: HashSet<i64> = progress.iter().map(|p| p.2).collect();
+    assert_eq!(ids, HashSet::from([100, 101, 102, 104, 105, 106]));
+    assert_eq!(progress.iter().filter(|p| !p.3).map(|p| p.2).collect::<Vec<_>>(), [102]);
+}
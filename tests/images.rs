@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use xdnmb_rs::cdnpath::ImageVariant;
+use xdnmb_rs::transport::{Request, Response, StreamSink, Transport};
+use xdnmb_rs::{ApiClient, Error, Result, RetryPolicy};
+
+
+// 一张 100 字节的“PNG”
+fn image() -> Vec<u8> {
+    let mut data = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    data.extend((0..92).map(|i| i as u8));
+    data
+}
+
+// 图片请求依次按 steps 响应，记录每次请求的 Range
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    // 以给定状态码开始发送完整图片，写入 n 字节后断开
+    Drop(u16, usize),
+    // 完整的 200 响应，忽略 Range
+    Full,
+    // 按 Range 返回 206
+    Partial,
+    // 416，Content-Range 为 bytes */total
+    Unsatisfiable(u64),
+}
+
+#[derive(Debug)]
+struct CdnStub {
+    steps: Mutex<Vec<Step>>,
+    ranges: Mutex<Vec<Option<String>>>,
+}
+
+fn head(request: &Request, status: u16, headers: Vec<(String, String)>) -> Response {
+    Response { status, url: request.url.clone(), headers, body: Vec::new() }
+}
+
+#[async_trait]
+impl Transport for CdnStub {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let body = serde_json::json!([
+            {"url": "https://a.example/", "rate": 1.0},
+            {"url": "https://b.example/", "rate": 1.0},
+        ]);
+        Ok(Response { body: body.to_string().into_bytes(), ..head(&request, 200, Vec::new()) })
+    }
+
+    async fn send_streaming(&self, request: Request, sink: &mut dyn StreamSink) -> Result<Response> {
+        let range = request.headers.iter().find(|(k, _)| k == "range").map(|(_, v)| v.clone());
+        self.ranges.lock().unwrap().push(range.clone());
+        let step = self.steps.lock().unwrap().remove(0);
+        let data = image();
+        match step {
+            Step::Drop(status, n) => {
+                let start = match status {
+                    206 => range.and_then(|r| r.trim_start_matches("bytes=").trim_end_matches('-').parse().ok()).unwrap_or(0),
+                    _ => 0,
+                };
+                let headers = vec![("content-range".to_string(), format!("bytes {start}-99/100"))];
+                sink.start(&head(&request, status, headers)).await?;
+                sink.write_all(&data[start..start + n]).await?;
+                Err(Error::Io(std::io::Error::other("connection reset")))
+            }
+            Step::Full => {
+                let response = head(&request, 200, Vec::new());
+                sink.start(&response).await?;
+                sink.write_all(&data).await?;
+                Ok(response)
+            }
+            Step::Partial => {
+                let start: usize = range.unwrap().trim_start_matches("bytes=").trim_end_matches('-').parse().unwrap();
+                let headers = vec![("content-range".to_string(), format!("bytes {start}-99/100"))];
+                let response = head(&request, 206, headers);
+                sink.start(&response).await?;
+                sink.write_all(&data[start..]).await?;
+                Ok(response)
+            }
+            Step::Unsatisfiable(total) => {
+                let headers = vec![("content-range".to_string(), format!("bytes */{total}"))];
+                Ok(head(&request, 416, headers))
+            }
+        }
+    }
+}
+
+fn stub_client(name: &str, steps: Vec<Step>) -> (ApiClient, Arc<CdnStub>) {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-images-{}-{name}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let stub = Arc::new(CdnStub { steps: Mutex::new(steps), ranges: Mutex::new(Vec::new()) });
+    let client = ApiClient::builder()
+        .transport(stub.clone())
+        .image_cache_dir(&dir)
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .build()
+        .unwrap();
+    (client, stub)
+}
+
+async fn download(name: &str, steps: Vec<Step>) -> (Result<PathBuf>, Vec<Option<String>>) {
+    let (client, stub) = stub_client(name, steps);
+    let result = client.download_image_file(ImageVariant::Image, "2022-06-18/62acedc59ef24", ".png").await;
+    let ranges = stub.ranges.lock().unwrap().clone();
+    (result, ranges)
+}
+
+
+#[tokio::test]
+async fn full_response_after_dropped_transfer_replaces_partial() {
+    let (result, ranges) = download("full", vec![Step::Drop(200, 40), Step::Full]).await;
+    assert_eq!(std::fs::read(result.unwrap()).unwrap(), image());
+    assert_eq!(ranges, [None, Some("bytes=40-".to_string())]);
+}
+
+#[tokio::test]
+async fn partial_response_resumes() {
+    let (result, ranges) = download("resume", vec![Step::Drop(200, 40), Step::Partial]).await;
+    assert_eq!(std::fs::read(result.unwrap()).unwrap(), image());
+    assert_eq!(ranges, [None, Some("bytes=40-".to_string())]);
+}
+
+#[tokio::test]
+async fn unsatisfiable_range_with_wrong_length_restarts() {
+    let steps = vec![Step::Drop(200, 40), Step::Unsatisfiable(100), Step::Full];
+    let (result, ranges) = download("restart", steps).await;
+    assert_eq!(std::fs::read(result.unwrap()).unwrap(), image());
+    assert_eq!(ranges, [None, Some("bytes=40-".to_string()), None]);
+}
+
+#[tokio::test]
+async fn unsatisfiable_range_with_matching_length_is_complete() {
+    let steps = vec![Step::Drop(200, 100), Step::Unsatisfiable(100)];
+    let (result, _) = download("complete", steps).await;
+    assert_eq!(std::fs::read(result.unwrap()).unwrap(), image());
+}
+
+#[tokio::test]
+async fn cached_image_is_not_downloaded_again() {
+    let (client, stub) = stub_client("cached", vec![Step::Full]);
+    let first = client.download_image_file(ImageVariant::Image, "2022-06-18/62acedc59ef24", ".png").await.unwrap();
+    let second = client.download_image_file(ImageVariant::Image, "2022-06-18/62acedc59ef24", ".png").await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(std::fs::read(&second).unwrap(), image());
+    assert_eq!(stub.ranges.lock().unwrap().len(), 1);
+}
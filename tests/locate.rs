@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use xdnmb_rs::transport::MockTransport;
+use xdnmb_rs::{ApiClient, Error, RetryPolicy};
+
+
+fn post(id: i64) -> serde_json::Value {
+    json!({"id": id, "fid": 4, "user_hash": "abcdefg", "now": "", "content": "", "img": "", "ext": ""})
+}
+
+// 串 100 共 40 条回复（1001~1040），每页 19 条
+fn thread_mock() -> Arc<MockTransport> {
+    let mock = Arc::new(MockTransport::new());
+    let ids: Vec<i64> = (1001..=1040).collect();
+    for (i, chunk) in ids.chunks(19).enumerate() {
+        let mut thread = post(100);
+        thread["ReplyCount"] = json!(40);
+        thread["Replies"] = chunk.iter().map(|&id| post(id)).collect();
+        mock.on_query("api/thread", &[("id", "100"), ("page", &(i + 1).to_string())], thread);
+    }
+    mock
+}
+
+fn client(mock: &Arc<MockTransport>) -> ApiClient {
+    ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .build()
+        .unwrap()
+}
+
+
+#[tokio::test]
+async fn locate_with_resto_and_cache() {
+    let mock = thread_mock();
+    let mut reply = post(1025);
+    reply["resto"] = json!(100);
+    mock.on_query("api/ref", &[("id", "1025")], reply);
+    let client = client(&mock);
+
+    let location = client.locate_post(1025).await.unwrap();
+    assert_eq!((location.tid, location.fid, location.page), (100, Some(4), Some(2)));
+    assert_eq!(location.thread_path(), "/t/100?page=2");
+
+    let requests = mock.requests().len();
+    assert_eq!(client.clone().locate_post(1025).await.unwrap(), location);
+    assert_eq!(mock.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn locate_from_ref_page() {
+    let mock = thread_mock();
+    mock.on_query("api/ref", &[("id", "1040")], post(1040));
+    mock.on_raw(
+        "Home/Forum/ref", &[("id", "1040")], 200,
+        r#"<div class="h-threads-item"><a href="/t/100?r=1040" class="h-threads-info-id">No.1040</a></div>"#,
+    );
+    let location = client(&mock).locate_post(1040).await.unwrap();
+    assert_eq!((location.tid, location.page), (100, Some(3)));
+}
+
+#[tokio::test]
+async fn unknown_parent_is_an_error() {
+    let mock = thread_mock();
+    mock.on_query("api/ref", &[("id", "1030")], post(1030));
+    mock.on_raw("Home/Forum/ref", &[("id", "1030")], 200, "<div>该串不存在</div>");
+    let result = client(&mock).locate_post(1030).await;
+    assert!(matches!(result, Err(Error::UnknownParent(1030))), "{result:?}");
+}
+
+#[tokio::test]
+async fn missing_page_is_not_cached() {
+    let mock = thread_mock();
+    let mut reply = post(1050);
+    reply["resto"] = json!(100);
+    mock.on_query("api/ref", &[("id", "1050")], reply);
+    let client = client(&mock);
+
+    assert_eq!(client.locate_post(1050).await.unwrap().page, None);
+    assert!(client.location_cache().get(1050).is_none());
+}
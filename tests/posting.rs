@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use xdnmb_rs::transport::MockTransport;
+use xdnmb_rs::{ApiClient, Post, RetryPolicy};
+
+
+const SUCCESS_PAGE: &str = r#"<div class="system-message"><h1>:)</h1><p class="success">回复成功</p>
+<p class="jump">页面自动 <a id="href" href="/t/50000000">跳转</a></p></div>"#;
+
+fn client(mock: &Arc<MockTransport>) -> ApiClient {
+    ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn resolve_failure_keeps_successful_post() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+    mock.on_raw("api/getLastPost", &[], 500, "Internal Server Error");
+
+    let outcome = client(&mock)
+        .send_post(Post::reply(50000000).content("hello").resolve_id(true))
+        .await
+        .unwrap();
+    assert_eq!(outcome.message, "回复成功");
+    assert!(outcome.post.is_none());
+    assert!(outcome.resolve_error.unwrap().contains("500"));
+}
+
+#[tokio::test]
+async fn resolve_quote_reply() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+    mock.on("api/getLastPost", serde_json::json!({
+        "id": 50000001, "fid": 4, "resto": 50000000, "user_hash": "abcdefg",
+        "now": "2025-07-31(四)13:49:32", "img": "", "ext": "",
+        "content": "<font color=\"#789922\">&gt;&gt;No.50000000</font><br />\nhello",
+    }));
+
+    let outcome = client(&mock)
+        .send_post(Post::reply(50000000).content(">>No.50000000\nhello").resolve_id(true))
+        .await
+        .unwrap();
+    assert_eq!(outcome.post.map(|post| *post.tid), Some(50000001));
+    assert!(outcome.resolve_error.is_none());
+}
+
+#[tokio::test]
+async fn stream_attachment_is_uploaded() {
+    use futures::stream::{self, BoxStream, StreamExt};
+    use xdnmb_rs::attachment::Attachment;
+    use xdnmb_rs::transport::{Body, Part};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.on_raw("Home/Forum/doReplyThread.html", &[], 200, SUCCESS_PAGE);
+
+    // BoxStream 只满足 Send，不满足 Sync
+    let chunks: BoxStream<'static, std::io::Result<bytes::Bytes>> = stream::iter([
+        Ok(bytes::Bytes::from_static(b"GIF89a")),
+        Ok(bytes::Bytes::from_static(b"...")),
+    ]).boxed();
+    let post = Post::reply(50000000)
+        .attachment(Attachment::from_stream(chunks, Some(9), "a.gif", "image/gif"))
+        .process_image(false);
+    // 发串的 future 可以交给其他任务执行
+    let client = client(&mock);
+    tokio::spawn(async move { client.send_post(post).await }).await.unwrap().unwrap();
+
+    let requests = mock.requests();
+    let Body::Multipart(parts) = &requests[0].body else { panic!("expected multipart body") };
+    let attachment = parts.iter().find_map(|part| match part {
+        Part::File { attachment, .. } => attachment.try_clone(),
+        _ => None,
+    }).unwrap();
+    assert_eq!(attachment.into_bytes().await.unwrap().as_ref(), b"GIF89a...");
+}
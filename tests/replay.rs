@@ -0,0 +1,195 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use xdnmb_rs::transport::{Body, MockTransport, RecordingTransport, ReplayTransport, Request, Response, Transport};
+use xdnmb_rs::{ApiClient, ApiError, Error, Post, Result, RetryPolicy};
+
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+fn replay_client() -> ApiClient {
+    ApiClient::builder()
+        .transport(ReplayTransport::new(fixtures()))
+        .feed_uuid("00000000-0000-0000-0000-000000000000")
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .build()
+        .unwrap()
+}
+
+
+#[tokio::test]
+async fn replay_forum_and_timeline_lists() {
+    let client = replay_client();
+    client.init().await.unwrap();
+    let cdn = client.cdn_path_list().unwrap();
+    assert_eq!(cdn[0].url, "https://image.nmb.best/");
+
+    let forums = client.get_forum_list().await.unwrap();
+    let forums: Vec<_> = forums.iter().flat_map(|group| group.forums.iter()).collect();
+    assert_eq!(forums.len(), 3);
+    assert_eq!(*forums[0].fid, -1);
+    assert!(forums[0].interval.is_none());
+    assert_eq!(forums[1].interval.map(|n| n.into_inner()), Some(30));
+    assert_eq!(forums[1].thread_count.map(|n| n.into_inner()), Some(5345678));
+    // 空字符串按 0 处理，布尔值可能是字符串或数字
+    assert_eq!(forums[2].interval.map(|n| n.into_inner()), Some(0));
+    assert_eq!(forums[1].safe_mode.map(|b| b.into_inner()), Some(false));
+    assert_eq!(forums[2].auto_delete.map(|b| b.into_inner()), Some(true));
+    assert_eq!(forums[2].permission_level.map(|b| b.into_inner()), Some(false));
+
+    assert_eq!(client.get_timeline_list().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn replay_thread_pages() {
+    let client = replay_client();
+    let thread = client.get_thread_page(50000000, 1, false).await.unwrap();
+    assert_eq!(*thread.tid, 50000000);
+    assert_eq!(thread.reply_count.map(|n| n.into_inner()), Some(3));
+    assert_eq!(thread.sage.map(|b| b.into_inner()), Some(true));
+    assert_eq!(thread.tips.as_ref().map(|tips| tips.id), Some(9999999));
+    let replies = thread.replies.unwrap();
+    assert_eq!(replies.iter().map(|r| *r.tid).collect::<Vec<_>>(), [50000001, 50000002, 50000003]);
+
+    let po = client.get_thread_page(50000000, 1, true).await.unwrap();
+    assert_eq!(po.replies.unwrap().len(), 1);
+
+    let reply = client.get_reply(50000001).await.unwrap();
+    assert_eq!(reply.user_hash, "hijklmn");
+
+    match client.get_thread_page(1, 1, false).await {
+        Err(Error::Api(ApiError::ThreadNotFound(_))) => {}
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn replay_thread_lists() {
+    let client = replay_client();
+    let threads = client.get_threads_from_forum(4, 1).await.unwrap();
+    assert_eq!(threads[0].remain_replies.map(|n| n.into_inner()), Some(2));
+    assert_eq!(client.get_threads_from_timeline(1, 1).await.unwrap().len(), 1);
+
+    let feed = client.get_threads_from_feed(1).await.unwrap();
+    let recent: Vec<i64> = feed[0].recent_replies.as_ref().unwrap().iter().map(|n| n.into_inner()).collect();
+    assert_eq!(recent, [50000001, 50000002, 50000003]);
+    assert_eq!(feed[0].hide.map(|b| b.into_inner()), Some(false));
+}
+
+#[tokio::test]
+async fn missing_fixture_is_an_error() {
+    let result = replay_client().get_thread_page(50000000, 2, false).await;
+    assert!(matches!(result, Err(Error::FixtureMissing(_))));
+}
+
+#[tokio::test]
+async fn record_then_replay() {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-record-{}", std::process::id()));
+    let mock = MockTransport::new();
+    mock.on_query("api/ref", &[("id", "50000001")], serde_json::json!({
+        "id": "50000001", "img": "", "ext": "", "now": "2022-06-18(六)12:34:56",
+        "user_hash": "hijklmn", "content": "回复", "admin": "0",
+    }));
+    let recording = ApiClient::builder()
+        .transport(RecordingTransport::new(mock, &dir))
+        .no_rate_limit()
+        .build()
+        .unwrap();
+    let recorded = recording.get_reply(50000001).await.unwrap();
+
+    let replay = ApiClient::builder()
+        .transport(ReplayTransport::new(&dir))
+        .no_rate_limit()
+        .build()
+        .unwrap();
+    let replayed = replay.get_reply(50000001).await.unwrap();
+    assert_eq!(*recorded.tid, *replayed.tid);
+    assert_eq!(recorded.content, replayed.content);
+    std::fs::remove_dir_all(dir).ok();
+}
+
+// 把表单字段原样作为 JSON 返回，用于区分请求体不同的请求
+#[derive(Debug)]
+struct EchoForm;
+
+#[async_trait]
+impl Transport for EchoForm {
+    async fn send(&self, request: Request) -> Result<Response> {
+        let fields: serde_json::Map<String, serde_json::Value> = match &request.body {
+            Body::Form(fields) => fields.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect(),
+            _ => Default::default(),
+        };
+        let body = if request.url.ends_with("doReplyThread.html") {
+            r#"<h1>:)</h1><p class="success">回复成功</p><a id="href" href="/t/1">跳转</a>"#.as_bytes().to_vec()
+        } else {
+            serde_json::Value::Object(fields).to_string().into_bytes()
+        };
+        Ok(Response { status: 200, url: request.url, headers: Vec::new(), body })
+    }
+}
+
+fn client(transport: impl Transport + 'static) -> ApiClient {
+    ApiClient::builder()
+        .transport(transport)
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn form_fields_select_fixture() {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-record-form-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let recording = client(RecordingTransport::new(EchoForm, &dir));
+    let uuid = "00000000-0000-0000-0000-000000000000";
+    recording.add_feed(uuid, "1").await.unwrap();
+    recording.add_feed(uuid, "2").await.unwrap();
+    recording.del_feed(uuid, "1").await.unwrap();
+
+    let replay = client(ReplayTransport::new(&dir));
+    assert_eq!(replay.add_feed(uuid, "1").await.unwrap()["tid"], "1");
+    assert_eq!(replay.add_feed(uuid, "2").await.unwrap()["tid"], "2");
+    assert_eq!(replay.del_feed(uuid, "1").await.unwrap()["tid"], "1");
+    assert!(matches!(replay.del_feed(uuid, "2").await, Err(Error::FixtureMissing(_))));
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn long_post_fixture_name_is_shortened() {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-record-post-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let recording = client(RecordingTransport::new(EchoForm, &dir));
+    let content = "正文".repeat(500);
+    recording.send_post(Post::reply(1).content(&content)).await.unwrap();
+
+    let replay = client(ReplayTransport::new(&dir));
+    replay.send_post(Post::reply(1).content(&content)).await.unwrap();
+    assert!(matches!(replay.send_post(Post::reply(1).content("其他")).await, Err(Error::FixtureMissing(_))));
+    let names: Vec<_> = std::fs::read_dir(dir.join("Home/Forum/doReplyThread.html")).unwrap()
+        .map(|entry| entry.unwrap().file_name().len())
+        .collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().all(|&len| len <= 130), "{names:?}");
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn invalid_fixture_is_an_io_error() {
+    let dir = std::env::temp_dir().join(format!("xdnmb-rs-invalid-fixture-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("api/getCDNPath")).unwrap();
+    std::fs::write(dir.join("api/getCDNPath/GET.json"), "not json").unwrap();
+
+    match client(ReplayTransport::new(&dir)).refresh_cdn().await {
+        Err(Error::Io(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            assert!(e.to_string().contains("GET.json"), "{e}");
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use futures::{StreamExt, TryStreamExt};
+use serde_json::json;
+use xdnmb_rs::paging::PageStreamOptions;
+use xdnmb_rs::transport::MockTransport;
+use xdnmb_rs::{ApiClient, RetryPolicy};
+
+
+fn post(id: i64) -> serde_json::Value {
+    json!({"id": id, "fid": 4, "user_hash": "abcdefg", "now": "", "content": "", "img": "", "ext": ""})
+}
+
+// 版块 4 的一页串列表
+fn forum_page(mock: &MockTransport, page: usize, ids: impl IntoIterator<Item = i64>) {
+    let threads: Vec<_> = ids.into_iter().map(post).collect();
+    mock.on_query("api/showf", &[("id", "4"), ("page", &page.to_string())], json!(threads));
+}
+
+// 串 100 共 24 条回复，两页
+fn thread_mock() -> Arc<MockTransport> {
+    let mock = Arc::new(MockTransport::new());
+    for (page, ids) in [(1, 1001..=1019), (2, 1020..=1024)] {
+        let mut thread = post(100);
+        thread["ReplyCount"] = json!(24);
+        thread["Replies"] = ids.map(post).collect();
+        mock.on_query("api/thread", &[("id", "100"), ("page", &page.to_string())], thread);
+    }
+    mock
+}
+
+fn client(mock: &Arc<MockTransport>) -> ApiClient {
+    ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap()
+}
+
+fn requested_pages(mock: &MockTransport) -> Vec<String> {
+    mock.requests().iter()
+        .filter_map(|r| r.query.iter().find(|(k, _)| k == "page").map(|(_, v)| v.clone()))
+        .collect()
+}
+
+
+#[tokio::test]
+async fn thread_stream_stops_at_last_page() {
+    let mock = thread_mock();
+    let client = client(&mock);
+    let replies: Vec<_> = client.thread_replies_stream(100, false, &PageStreamOptions::new())
+        .map_ok(|reply| *reply.tid)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(replies, (1001..=1024).collect::<Vec<_>>());
+    assert_eq!(requested_pages(&mock), ["1", "2"]);
+}
+
+#[tokio::test]
+async fn forum_stream_stops_at_empty_page() {
+    let mock = Arc::new(MockTransport::new());
+    forum_page(&mock, 1, [1, 2]);
+    forum_page(&mock, 2, [3]);
+    forum_page(&mock, 3, []);
+    forum_page(&mock, 4, [4]);
+
+    let client = client(&mock);
+    let threads: Vec<_> = client.forum_threads_stream(4, &PageStreamOptions::new())
+        .map_ok(|thread| *thread.tid)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(threads, [1, 2, 3]);
+    assert_eq!(requested_pages(&mock), ["1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn forum_stream_stops_at_page_out_of_range() {
+    let mock = Arc::new(MockTransport::new());
+    forum_page(&mock, 1, [1, 2]);
+    mock.on_query("api/showf", &[("id", "4"), ("page", "2")], json!("页数超出范围"));
+
+    let client = client(&mock);
+    let threads: Vec<_> = client.forum_threads_stream(4, &PageStreamOptions::new())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(threads.len(), 2);
+}
+
+#[tokio::test]
+async fn stream_errors_end_the_stream() {
+    let mock = Arc::new(MockTransport::new());
+    forum_page(&mock, 1, [1]);
+    mock.on_raw("api/showf", &[("id", "4"), ("page", "2")], 500, "Internal Server Error");
+    forum_page(&mock, 3, [3]);
+
+    let client = client(&mock);
+    let items: Vec<_> = client.forum_threads_stream(4, &PageStreamOptions::new()).collect().await;
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_ok());
+    assert!(items[1].is_err());
+}
+
+#[tokio::test]
+async fn start_page_and_max_pages() {
+    let mock = Arc::new(MockTransport::new());
+    for page in 1..=5 {
+        forum_page(&mock, page, [page as i64 * 10, page as i64 * 10 + 1]);
+    }
+
+    let client = client(&mock);
+    let options = PageStreamOptions::new().start_page(2).max_pages(2);
+    let threads: Vec<_> = client.forum_threads_stream(4, &options)
+        .map_ok(|thread| *thread.tid)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(threads, [20, 21, 30, 31]);
+    assert_eq!(requested_pages(&mock), ["2", "3"]);
+}
+
+#[tokio::test]
+async fn prefetched_pages_after_last_are_dropped() {
+    let mock = thread_mock();
+    // 第 3 页本不应存在，第 4 页以后没有设置，请求会出错
+    let mut extra = post(100);
+    extra["ReplyCount"] = json!(24);
+    extra["Replies"] = json!([post(9001)]);
+    mock.on_query("api/thread", &[("id", "100"), ("page", "3")], extra);
+
+    let client = client(&mock);
+    let options = PageStreamOptions::new().prefetch(3);
+    let replies: Vec<_> = client.thread_replies_stream(100, false, &options)
+        .map_ok(|reply| *reply.tid)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(replies, (1001..=1024).collect::<Vec<_>>());
+    // 确实提前请求了最后一页之后的页
+    assert!(requested_pages(&mock).contains(&"3".to_string()));
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use xdnmb_rs::transport::MockTransport;
+use xdnmb_rs::forum::Thread;
+use xdnmb_rs::refresh::ThreadChange;
+use xdnmb_rs::{ApiClient, RetryPolicy};
+
+
+fn post(id: i64) -> serde_json::Value {
+    json!({"id": id, "fid": 4, "user_hash": "abcdefg", "now": "", "content": "", "img": "", "ext": ""})
+}
+
+// 设置串 100 某一页的内容，reply_count 为读取该页时整串的回复数
+fn page(mock: &MockTransport, page: usize, reply_count: i64, ids: impl IntoIterator<Item = i64>) {
+    let mut thread = post(100);
+    thread["ReplyCount"] = json!(reply_count);
+    thread["Replies"] = ids.into_iter().map(post).collect();
+    mock.on_query("api/thread", &[("id", "100"), ("page", &page.to_string())], thread);
+}
+
+// 之前读取到的串，回复为 ids
+fn previous(ids: impl IntoIterator<Item = i64>) -> Thread {
+    let mut thread = post(100);
+    thread["Replies"] = ids.into_iter().map(post).collect();
+    thread["ReplyCount"] = json!(thread["Replies"].as_array().unwrap().len());
+    serde_json::from_value(thread).unwrap()
+}
+
+fn client(mock: &Arc<MockTransport>) -> ApiClient {
+    ApiClient::builder()
+        .transport(mock.clone())
+        .retry_policy(RetryPolicy::none())
+        .no_rate_limit()
+        .no_cooldown()
+        .build()
+        .unwrap()
+}
+
+fn reply_ids(thread: &Thread) -> Vec<i64> {
+    thread.replies.as_ref().unwrap().iter().map(|reply| *reply.tid).collect()
+}
+
+fn requested_pages(mock: &MockTransport) -> Vec<String> {
+    let mut pages: Vec<String> = mock.requests().iter()
+        .filter(|r| r.url.ends_with("api/thread"))
+        .filter_map(|r| r.query.iter().find(|(k, _)| k == "page").map(|(_, v)| v.clone()))
+        .collect();
+    pages.sort();
+    pages
+}
+
+
+#[tokio::test]
+async fn full_thread_reads_pages_added_during_fetch() {
+    let mock = Arc::new(MockTransport::new());
+    // 读第 1 页时共 37 条回复（2 页），读第 2 页时已填满并出现了第 3 页
+    page(&mock, 1, 37, 1001..=1019);
+    page(&mock, 2, 39, 1020..=1038);
+    page(&mock, 3, 39, [1039]);
+
+    let thread = client(&mock).get_full_thread(100).await.unwrap();
+    assert_eq!(reply_ids(&thread), (1001..=1039).collect::<Vec<_>>());
+    assert_eq!(*thread.reply_count.unwrap(), 39);
+    assert_eq!(requested_pages(&mock), ["1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn full_thread_stops_at_empty_tail_page() {
+    let mock = Arc::new(MockTransport::new());
+    // 第 2 页刚好填满，但没有更多回复
+    page(&mock, 1, 38, 1001..=1019);
+    page(&mock, 2, 39, 1020..=1038);
+    page(&mock, 3, 38, []);
+
+    let thread = client(&mock).get_full_thread(100).await.unwrap();
+    assert_eq!(reply_ids(&thread), (1001..=1038).collect::<Vec<_>>());
+    assert_eq!(requested_pages(&mock), ["1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn full_thread_dedups_shifted_replies() {
+    let mock = Arc::new(MockTransport::new());
+    // 读取期间前面的回复被删除，1019 和 1038 同时出现在相邻两页
+    page(&mock, 1, 57, 1001..=1019);
+    page(&mock, 2, 56, 1019..=1038);
+    page(&mock, 3, 56, 1038..=1055);
+
+    let thread = client(&mock).get_full_thread_with(100, 3).await.unwrap();
+    assert_eq!(reply_ids(&thread), (1001..=1055).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn full_thread_stops_at_page_that_shrank() {
+    let mock = Arc::new(MockTransport::new());
+    // 读第 1 页时有 3 页，之后回复被删除，第 3 页已不存在
+    page(&mock, 1, 57, 1001..=1019);
+    page(&mock, 2, 38, 1020..=1038);
+    mock.on_query("api/thread", &[("id", "100"), ("page", "3")], json!("页数超出范围"));
+
+    let thread = client(&mock).get_full_thread(100).await.unwrap();
+    assert_eq!(reply_ids(&thread), (1001..=1038).collect::<Vec<_>>());
+    assert_eq!(requested_pages(&mock), ["1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn refresh_reads_new_replies_across_pages() {
+    let mock = Arc::new(MockTransport::new());
+    page(&mock, 1, 60, 1001..=1019);
+    page(&mock, 2, 60, 1020..=1038);
+    page(&mock, 3, 60, 1039..=1057);
+    page(&mock, 4, 60, 1058..=1060);
+
+    let update = client(&mock).refresh_thread(&previous(1001..=1030)).await.unwrap();
+    assert_eq!(update.new_replies.iter().map(|r| *r.tid).collect::<Vec<_>>(), (1031..=1060).collect::<Vec<_>>());
+    assert!(update.changes.is_empty());
+    assert!(update.head.is_some());
+    // 第 2 页已有已知的回复，不再往前读
+    assert_eq!(requested_pages(&mock), ["1", "2", "3", "4"]);
+}
+
+#[tokio::test]
+async fn refresh_without_new_replies() {
+    let mock = Arc::new(MockTransport::new());
+    page(&mock, 1, 38, 1001..=1019);
+    page(&mock, 2, 38, 1020..=1038);
+
+    let update = client(&mock).refresh_thread(&previous(1001..=1038)).await.unwrap();
+    assert!(!update.has_changes());
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn refresh_reports_sage_and_hide() {
+    let mock = Arc::new(MockTransport::new());
+    let mut thread = post(100);
+    thread["ReplyCount"] = json!(2);
+    thread["Replies"] = json!([post(1001), post(1002)]);
+    thread["sage"] = json!("1");
+    thread["Hide"] = json!("1");
+    mock.on_query("api/thread", &[("id", "100"), ("page", "1")], thread);
+
+    let update = client(&mock).refresh_thread(&previous([1001])).await.unwrap();
+    assert_eq!(update.changes, [ThreadChange::Sage(true), ThreadChange::Hidden(true)]);
+    assert_eq!(update.new_replies.iter().map(|r| *r.tid).collect::<Vec<_>>(), [1002]);
+}
+
+#[tokio::test]
+async fn refresh_deleted_thread() {
+    let mock = Arc::new(MockTransport::new());
+    mock.on_query("api/thread", &[("id", "100")], json!("该串不存在"));
+
+    let update = client(&mock).refresh_thread(&previous(1001..=1010)).await.unwrap();
+    assert!(update.is_deleted());
+    assert!(update.head.is_none());
+    assert!(update.new_replies.is_empty());
+}